@@ -1,3 +1,5 @@
+use crate::actions::validate_action;
+use crate::fen::{parse_square, square_to_string};
 use crate::state::{Piece, State, PT};
 
 #[derive(Clone, PartialEq, Eq, Copy, Debug)]
@@ -35,13 +37,6 @@ pub enum Action {
     Tie,
 }
 
-fn to_row(i: usize) -> usize {
-    8 - i
-}
-fn to_column(i: usize) -> char {
-    (97 + i as u8) as char
-}
-
 impl Action {
     pub fn get_main_coords(&self) -> (usize, usize) {
         match self {
@@ -164,78 +159,52 @@ impl Action {
         let coords = self.get_main_coords();
         state.board[coords.0][coords.1]
     }
-    pub fn to_string(&self, state: &State) -> String {
+    // Long algebraic coordinate notation as used by UCI and xboard, e.g. "e2e4" or "e7e8q".
+    pub fn to_uci(&self) -> String {
         if *self == Action::Tie {
-            return "½–½".to_string();
+            return "0000".to_string();
         }
 
-        let piece_char = self.get_main_piece(state).unwrap().piece_to_char();
-
-        match *self {
-            Action::Jump { s_y, s_x, e_y, e_x } => {
-                let s_row = to_row(s_y);
-                let s_column = to_column(s_x);
-                let e_row = to_row(e_y);
-                let e_column = to_column(e_x);
-
-                format!("{} {}{}{}{}", piece_char, s_column, s_row, e_column, e_row)
-            }
-            Action::Capture { s_y, s_x, e_y, e_x } => {
-                let s_row = to_row(s_y);
-                let s_column = to_column(s_x);
-                let e_row = to_row(e_y);
-                let e_column = to_column(e_x);
-
-                format!("{} {}{}x{}{}", piece_char, s_column, s_row, e_column, e_row)
-            }
-            Action::Castling { queenside, .. } => {
-                if queenside {
-                    "0-0-0".to_string()
-                } else {
-                    "0-0".to_string()
-                }
-            }
-            Action::Promotion {
-                s_y,
-                s_x,
-                e_y,
-                e_x,
-                to_piece,
-            } => {
-                let piece_color = self.get_main_piece(state).unwrap().c;
-
-                let s_row = to_row(s_y);
-                let s_column = to_column(s_x);
-                let e_row = to_row(e_y);
-                let e_column = to_column(e_x);
-                let promo_char = Piece {
-                    c: piece_color,
-                    t: to_piece,
-                }
-                .piece_to_char();
-
-                let x_if_capture = match state.board[e_y][e_x] {
-                    None => "",
-                    Some(_) => "x",
-                };
+        let (s_y, s_x) = self.get_main_coords();
+        let (e_y, e_x) = self.get_end_coords();
+        let promotion = match *self {
+            Action::Promotion { to_piece, .. } => match to_piece {
+                PT::Knight => "n",
+                PT::Bishop => "b",
+                PT::Rook { .. } => "r",
+                _ => "q",
+            },
+            _ => "",
+        };
 
-                format!(
-                    "{} {}{}{}{}{}{}",
-                    piece_char, s_column, x_if_capture, s_row, e_column, e_row, promo_char
-                )
-            }
-            Action::Enpassant { s_y, s_x, e_y, e_x } => {
-                let s_row = to_row(s_y);
-                let s_column = to_column(s_x);
-                let e_row = to_row(e_y);
-                let e_column = to_column(e_x);
+        format!(
+            "{}{}{}",
+            square_to_string(s_y, s_x),
+            square_to_string(e_y, e_x),
+            promotion
+        )
+    }
+    pub fn from_uci(state: &State, s: &str) -> Option<Action> {
+        if s.len() != 4 && s.len() != 5 {
+            return None;
+        }
+        let start_coords = parse_square(s.get(0..2)?)?;
+        let end_coords = parse_square(s.get(2..4)?)?;
+        let promotion_piece = match s.get(4..) {
+            Some("") => None,
+            Some("q") => Some(PT::Queen),
+            Some("r") => Some(PT::Rook { has_moved: true }),
+            Some("b") => Some(PT::Bishop),
+            Some("n") => Some(PT::Knight),
+            _ => return None,
+        };
 
-                format!(
-                    "{} {}{}x{}{} e.p.",
-                    piece_char, s_column, s_row, e_column, e_row
-                )
-            }
-            Action::Tie => "½–½".to_string(),
+        let action =
+            Action::from_context_and_coords(state, start_coords, end_coords, promotion_piece)?;
+        if validate_action(state, &action) {
+            Some(action)
+        } else {
+            None
         }
     }
 }
@@ -1,651 +1,262 @@
 use crate::action::Action;
-use crate::result::result;
-use crate::state::{Piece, State, PC, PT};
-
-use std::cmp;
-use std::convert::TryFrom;
-use std::convert::TryInto;
-
-fn valid_pawn_jump(state: &State, s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    let main_piece = state.board[s_y][s_x].unwrap();
-
-    if main_piece.c == PC::Black {
-        (e_y == 3 && s_y == 1 && state.board[2][s_x].is_none() && e_x == s_x)
-            || (e_y == s_y + 1 && e_x == s_x)
-    } else {
-        (e_y == 4 && s_y == 6 && state.board[5][s_x].is_none() && e_x == s_x)
-            || (e_y + 1 == s_y && e_x == s_x)
-    }
-}
-
-fn valid_pawn_capture(state: &State, s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    let main_piece = state.board[s_y][s_x].unwrap();
-
-    if main_piece.c == PC::Black {
-        e_y == s_y + 1 && (e_x + 1 == s_x || e_x == s_x + 1)
-    } else {
-        e_y + 1 == s_y && (e_x + 1 == s_x || e_x == s_x + 1)
-    }
-}
-
-fn valid_knight_jump(s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    let (y_delta, x_delta) = (e_y as isize - s_y as isize, e_x as isize - s_x as isize);
-    let (y_delta_abs, x_delta_abs) = (y_delta.abs(), x_delta.abs());
-    let (sorted_delta_1, sorted_delta_2) = {
-        if y_delta_abs <= x_delta_abs {
-            (y_delta_abs, x_delta_abs)
+use crate::bitboard::{
+    bishop_attacks, bit, color_index, coords, king_attacks, knight_attacks, pawn_attacks,
+    queen_attacks, rook_attacks, square, squares, Bitboard, BISHOP, KING, KNIGHT, PAWN, QUEEN,
+    ROOK,
+};
+use crate::state::{Piece, State, FIFTY_MOVE_RULE_PLIES, PC, PT};
+
+const PROMOTION_TYPES: [PT; 4] = [
+    PT::Queen,
+    PT::Rook { has_moved: true },
+    PT::Bishop,
+    PT::Knight,
+];
+
+// Adds a Jump or Capture from s to every square in targets.
+fn push_moves(state: &State, s: usize, targets: Bitboard, found: &mut Vec<Action>) {
+    let (s_y, s_x) = coords(s);
+    for e in squares(targets) {
+        let (e_y, e_x) = coords(e);
+        if state.board[e_y][e_x].is_some() {
+            found.push(Action::Capture { s_y, s_x, e_y, e_x });
         } else {
-            (x_delta_abs, y_delta_abs)
-        }
-    };
-
-    sorted_delta_1 == 1 && sorted_delta_2 == 2
-}
-
-fn valid_bishop_jump(state: &State, s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    let d_y = if s_y < e_y { 1 } else { -1 };
-    let d_x = if s_x < e_x { 1 } else { -1 };
-    let steps = (e_y as isize - s_y as isize).abs();
-
-    if e_y as isize != s_y as isize + d_y * steps
-        || e_x as isize != s_x as isize + d_x as isize * steps
-    {
-        return false;
-    }
-
-    for i in 1..steps {
-        let (c_y, c_x) = (
-            (s_y as isize + d_y as isize * i) as usize,
-            (s_x as isize + d_x as isize * i) as usize,
-        );
-        if state.board[c_y][c_x].is_some() {
-            return false;
-        }
-    }
-
-    true
-}
-
-fn valid_rook_jump(state: &State, s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    if e_y == s_y {
-        let d_x = if s_x < e_x { 1 } else { -1 };
-        let steps = (e_x as isize - s_x as isize).abs();
-        for i in 1..steps {
-            let c_x = (s_x as isize + d_x as isize * i) as usize;
-            if state.board[e_y][c_x].is_some() {
-                return false;
-            }
-        }
-
-        true
-    } else if e_x == s_x {
-        let d_y = if s_y < e_y { 1 } else { -1 };
-        let steps = (e_y as isize - s_y as isize).abs();
-        for i in 1..steps {
-            let c_y = (s_y as isize + d_y as isize * i) as usize;
-            if state.board[c_y][e_x].is_some() {
-                return false;
-            }
+            found.push(Action::Jump { s_y, s_x, e_y, e_x });
         }
-
-        true
-    } else {
-        false
     }
 }
 
-fn valid_queen_jump(state: &State, s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    valid_rook_jump(state, s_y, s_x, e_y, e_x) || valid_bishop_jump(state, s_y, s_x, e_y, e_x)
-}
-
-fn valid_king_jump(s_y: usize, s_x: usize, e_y: usize, e_x: usize) -> bool {
-    cmp::max(
-        (e_y as isize - s_y as isize).abs(),
-        (e_x as isize - s_x as isize).abs(),
-    ) == 1
-}
-
-fn validate_action_ignore_check(state: &State, action: &Action) -> bool {
-    let main_piece = match action.get_main_piece(state) {
-        Some(piece) => piece,
-        None => {
-            return false;
-        }
+fn push_pawn_moves(state: &State, found: &mut Vec<Action>) {
+    let me = state.turn;
+    let occupied = state.bitboards.occupied();
+    let theirs = state.bitboards.colors[color_index(me.opponent())];
+    let (forward, start_y, last_y, enpassant_y): (isize, usize, usize, usize) = match me {
+        PC::White => (-1, 6, 0, 3),
+        PC::Black => (1, 1, 7, 4),
     };
 
-    if main_piece.c != state.turn {
-        return false;
-    }
-    match *action {
-        Action::Jump { s_y, s_x, e_y, e_x } => {
-            if state.board[s_y][s_x].is_none() {
-                return false;
-            }
-            if state.board[e_y][e_x].is_some() {
-                return false;
+    for s in squares(state.bitboards.of(me, PAWN)) {
+        let (s_y, s_x) = coords(s);
+        let e_y = (s_y as isize + forward) as usize;
+
+        let mut push = |e_y: usize, e_x: usize, capture: bool| {
+            if e_y == last_y {
+                for to_piece in PROMOTION_TYPES.iter() {
+                    found.push(Action::Promotion {
+                        s_y,
+                        s_x,
+                        e_y,
+                        e_x,
+                        to_piece: *to_piece,
+                    });
+                }
+            } else if capture {
+                found.push(Action::Capture { s_y, s_x, e_y, e_x });
+            } else {
+                found.push(Action::Jump { s_y, s_x, e_y, e_x });
             }
+        };
+
+        if occupied & bit(square(e_y, s_x)) == 0 {
+            push(e_y, s_x, false);
 
-            match main_piece.t {
-                PT::Pawn { .. } => valid_pawn_jump(state, s_y, s_x, e_y, e_x),
-                PT::Knight => valid_knight_jump(s_y, s_x, e_y, e_x),
-                PT::Bishop => valid_bishop_jump(state, s_y, s_x, e_y, e_x),
-                PT::Rook { .. } => valid_rook_jump(state, s_y, s_x, e_y, e_x),
-                PT::Queen => valid_queen_jump(state, s_y, s_x, e_y, e_x),
-                PT::King { .. } => valid_king_jump(s_y, s_x, e_y, e_x),
+            let double_y = (e_y as isize + forward) as usize;
+            if s_y == start_y && occupied & bit(square(double_y, s_x)) == 0 {
+                push(double_y, s_x, false);
             }
         }
-        Action::Capture { s_y, s_x, e_y, e_x } => {
-            // check if jump is valid.
-            let capture_piece = match state.board[e_y][e_x] {
-                Some(p) => p,
-                None => {
-                    return false;
-                }
-            };
-            if main_piece.c == capture_piece.c {
-                return false;
-            }
 
-            match main_piece.t {
-                PT::Pawn { .. } => valid_pawn_capture(state, s_y, s_x, e_y, e_x),
-                PT::Knight => valid_knight_jump(s_y, s_x, e_y, e_x),
-                PT::Bishop => valid_bishop_jump(state, s_y, s_x, e_y, e_x),
-                PT::Rook { .. } => valid_rook_jump(state, s_y, s_x, e_y, e_x),
-                PT::Queen => valid_queen_jump(state, s_y, s_x, e_y, e_x),
-                PT::King { .. } => valid_king_jump(s_y, s_x, e_y, e_x),
-            }
+        for e in squares(pawn_attacks(me, s) & theirs) {
+            let (e_y, e_x) = coords(e);
+            push(e_y, e_x, true);
         }
-        Action::Castling {
-            s_y,
-            s_x,
-            queenside,
-        } => {
-            match main_piece.t {
-                PT::King { has_moved: false } => (),
-                _ => return false,
-            }
-            if s_y != 0 && s_y != 7 {
-                return false;
-            }
-            if queenside {
-                // row s_y must look like: R...K???
-                match state.board[s_y][0] {
-                    Some(Piece {
-                        c,
-                        t: PT::Rook { has_moved: false },
-                    }) if c == state.turn => (),
-                    _ => {
-                        return false;
-                    }
-                }
-                for i in 1..s_x {
-                    if state.board[s_y][i].is_some() {
-                        return false;
-                    }
-                }
-                // note: validate_action_ignore_check calls is_attacked, then is_attacked calls
-                //       validate_.... This is fine, since validate_... only calls is_attacked when
-                //       talking about a castling move.
-                for i in 0..=s_x {
-                    if is_attacked(state, (s_y, i), state.turn) {
-                        return false;
-                    }
-                }
 
-                true
-            } else {
-                // row s_y must look like: ????K..R
-                match state.board[s_y][7] {
+        // the captured pawn must have jumped over e on the previous move.
+        if s_y == enpassant_y && state.moves > 0 {
+            for e in squares(pawn_attacks(me, s) & !occupied) {
+                let (e_y, e_x) = coords(e);
+                match state.board[s_y][e_x] {
                     Some(Piece {
                         c,
-                        t: PT::Rook { has_moved: false },
-                    }) if c == state.turn => (),
-                    _ => {
-                        return false;
-                    }
-                }
-                for i in (s_x + 1)..7 {
-                    if state.board[s_y][i].is_some() {
-                        return false;
-                    }
-                }
-                // same note applies here as well.
-                for i in s_x..=7 {
-                    if is_attacked(state, (s_y, i), state.turn) {
-                        return false;
+                        t: PT::Pawn { last_jump },
+                    }) if c != me && last_jump == Some(state.moves - 1) => {
+                        found.push(Action::Enpassant { s_y, s_x, e_y, e_x });
                     }
+                    _ => (),
                 }
-
-                true
             }
         }
-        Action::Promotion {
-            s_y,
-            s_x,
-            e_y,
-            e_x,
-            to_piece,
-        } => {
-            match main_piece.t {
-                PT::Pawn { .. } => (),
-                _ => {
-                    return false;
-                }
-            }
-            match to_piece {
-                PT::Bishop => (),
-                PT::Rook { has_moved: true } => (),
-                PT::Knight => (),
-                PT::Queen => (),
-                _ => {
-                    return false;
-                }
-            }
-            if (main_piece.c == PC::Black && e_y != 7) || (main_piece.c == PC::White && e_y != 0) {
-                return false;
-            }
-            if state.board[e_y][e_x].is_some() {
-                valid_pawn_capture(state, s_y, s_x, e_y, e_x)
-            } else {
-                valid_pawn_jump(state, s_y, s_x, e_y, e_x)
-            }
-        }
-        Action::Enpassant { s_y, s_x, e_y, e_x } => {
-            let capture_piece = match state.board[s_y][e_x] {
-                Some(p) => p,
-                None => {
-                    return false;
-                }
-            };
-            if main_piece.c == capture_piece.c {
-                return false;
-            }
+    }
+}
 
-            match main_piece.t {
-                PT::Pawn { .. } => (),
-                _ => {
-                    return false;
-                }
-            }
-            match capture_piece.t {
-                PT::Pawn { last_jump } => {
-                    if state.moves != 0 && last_jump != Some(state.moves - 1) {
-                        return false;
-                    }
-                }
-                _ => {
-                    return false;
-                }
-            }
+// Castling rights are stored on the pieces: neither the king nor the rook may have moved.
+fn push_castling(state: &State, found: &mut Vec<Action>) {
+    let me = state.turn;
+    let s_y = match me {
+        PC::White => 7,
+        PC::Black => 0,
+    };
+    match state.board[s_y][4] {
+        Some(Piece {
+            c,
+            t: PT::King { has_moved: false },
+        }) if c == me => (),
+        _ => return,
+    }
 
-            match main_piece.c {
-                PC::Black => s_y == 4 && e_y == 5 && (e_x + 1 == s_x || e_x == s_x + 1),
-                PC::White => s_y == 3 && e_y == 2 && (e_x + 1 == s_x || e_x == s_x + 1),
-            }
+    for &queenside in [false, true].iter() {
+        // the squares between king and rook must be empty, and the king may not castle out of,
+        // through or into check.
+        let (rook_x, empty_xs, safe_xs) = if queenside {
+            (0, 1..4, 2..5)
+        } else {
+            (7, 5..7, 4..7)
+        };
+        match state.board[s_y][rook_x] {
+            Some(Piece {
+                c,
+                t: PT::Rook { has_moved: false },
+            }) if c == me => (),
+            _ => continue,
         }
-        Action::Tie => {
-            let hash = state.hash();
-
-            match state.hash_to_occurences.get(&hash) {
-                None => false,
-                Some(x) => *x >= 3,
-            }
+        if empty_xs.into_iter().any(|x| state.board[s_y][x].is_some()) {
+            continue;
+        }
+        if safe_xs
+            .into_iter()
+            .any(|x| state.bitboards.is_attacked(square(s_y, x), me.opponent()))
+        {
+            continue;
         }
+
+        found.push(Action::Castling {
+            s_y,
+            s_x: 4,
+            queenside,
+        });
     }
 }
 
-// ONLY used for castling/king.
-fn is_attacked(state: &State, position: (usize, usize), me: PC) -> bool {
-    let (e_y, e_x) = position;
-    for s_y in 0..8 {
-        for s_x in 0..8 {
-            if let Some(p) = state.board[s_y][s_x] {
-                let is_opponent_piece = p.c == me.opponent();
-                let can_piece_capture = match p.t {
-                    PT::Pawn { .. } => valid_pawn_capture(state, s_y, s_x, e_y, e_x),
-                    PT::Knight => valid_knight_jump(s_y, s_x, e_y, e_x),
-                    PT::Bishop => valid_bishop_jump(state, s_y, s_x, e_y, e_x),
-                    PT::Rook { .. } => valid_rook_jump(state, s_y, s_x, e_y, e_x),
-                    PT::Queen => valid_queen_jump(state, s_y, s_x, e_y, e_x),
-                    PT::King { .. } => valid_king_jump(s_y, s_x, e_y, e_x),
-                };
-
-                if is_opponent_piece && can_piece_capture {
-                    return true;
-                }
-            }
-        }
+// Generates every move that follows the movement rules, without checking whether it leaves the
+// king in check.
+fn pseudo_legal_actions(state: &State) -> Vec<Action> {
+    let mut found = Vec::with_capacity(64);
+    let me = state.turn;
+    let bitboards = &state.bitboards;
+    let occupied = bitboards.occupied();
+    let mine = bitboards.colors[color_index(me)];
+
+    push_pawn_moves(state, &mut found);
+    for s in squares(bitboards.of(me, KNIGHT)) {
+        push_moves(state, s, knight_attacks(s) & !mine, &mut found);
+    }
+    for s in squares(bitboards.of(me, BISHOP)) {
+        push_moves(state, s, bishop_attacks(s, occupied) & !mine, &mut found);
+    }
+    for s in squares(bitboards.of(me, ROOK)) {
+        push_moves(state, s, rook_attacks(s, occupied) & !mine, &mut found);
     }
+    for s in squares(bitboards.of(me, QUEEN)) {
+        push_moves(state, s, queen_attacks(s, occupied) & !mine, &mut found);
+    }
+    for s in squares(bitboards.of(me, KING)) {
+        push_moves(state, s, king_attacks(s) & !mine, &mut found);
+    }
+    push_castling(state, &mut found);
 
-    false
+    found
 }
 
-pub fn is_king_attacked(state: &State, me: PC) -> bool {
-    let (ky, kx) = {
-        let mut k = None;
-
-        'columns: for i in 0..8 {
-            for j in 0..8 {
-                if let Some(Piece {
-                    c,
-                    t: PT::King { .. },
-                }) = state.board[i][j]
-                {
-                    if c == me {
-                        k = Some((i, j));
-                        break 'columns;
-                    }
-                }
-            }
-        }
-
-        match k {
-            Some(k) => k,
-            None => panic!("Could not find king in board."),
-        }
+// Plays the action on a copy of the bitboards only, which is much cheaper than going through
+// result. Castling already checks every square the king passes through.
+fn leaves_king_safe(state: &State, action: &Action) -> bool {
+    let me = state.turn;
+    let mut bitboards = state.bitboards;
+
+    let (s_y, s_x) = action.get_main_coords();
+    let (e_y, e_x) = action.get_end_coords();
+    let main_piece = match state.board[s_y][s_x] {
+        Some(p) => p,
+        None => return false,
+    };
+    let captured = match *action {
+        Action::Castling { .. } => return true,
+        Action::Enpassant { s_y, e_x, .. } => (s_y, e_x),
+        _ => (e_y, e_x),
     };
 
-    is_attacked(state, (ky, kx), me)
-}
-
-pub fn validate_action(state: &State, action: &Action) -> bool {
-    if !validate_action_ignore_check(state, action) {
-        return false;
+    if let Some(captured_piece) = &state.board[captured.0][captured.1] {
+        bitboards.clear(square(captured.0, captured.1), captured_piece);
     }
+    bitboards.clear(square(s_y, s_x), &main_piece);
+    bitboards.set(square(e_y, e_x), &main_piece);
 
-    let after_state = result(state, action);
-    !is_king_attacked(&after_state, state.turn)
+    match bitboards.king_square(me) {
+        Some(king) => !bitboards.is_attacked(king, me.opponent()),
+        None => false,
+    }
 }
 
-fn within_bounds(y: isize, x: isize) -> Option<(usize, usize)> {
-    if y >= 8 || x >= 8 {
-        None
-    } else {
-        let y = usize::try_from(y).ok();
-        let x = usize::try_from(x).ok();
-
-        y.zip(x)
+pub fn is_king_attacked(state: &State, me: PC) -> bool {
+    match state.bitboards.king_square(me) {
+        Some(king) => state.bitboards.is_attacked(king, me.opponent()),
+        None => panic!("Could not find king in board."),
     }
 }
 
-fn interesting_locations(s_y: usize, s_x: usize, piece: &Piece) -> Vec<(usize, usize)> {
-    let deltas = match piece.t {
-        PT::Pawn { .. } => {
-            vec![
-                (-1, -1),
-                (-1, 1),
-                (1, -1),
-                (1, 1),
-                (1, 0),
-                (-1, 0),
-                (2, 0),
-                (-2, 0),
-            ]
-        }
-        PT::Knight => {
-            vec![
-                (-2, -1),
-                (-2, 1),
-                (-1, -2),
-                (-1, 2),
-                (1, -2),
-                (1, 2),
-                (2, -1),
-                (2, 1),
-            ]
-        }
-        PT::Bishop => {
-            let mut deltas = vec![];
-            for i in 1..8 {
-                deltas.push((-i, -i));
-                deltas.push((-i, i));
-                deltas.push((i, -i));
-                deltas.push((i, i));
-            }
-            deltas
-        }
-        PT::Rook { .. } => {
-            let mut deltas = vec![];
-            for i in 1..8 {
-                deltas.push((-i, 0));
-                deltas.push((0, -i));
-                deltas.push((0, i));
-                deltas.push((i, 0));
-            }
-            deltas
-        }
-        PT::Queen => {
-            let mut deltas = vec![];
-            for i in 1..8 {
-                deltas.push((-i, -i));
-                deltas.push((-i, i));
-                deltas.push((i, -i));
-                deltas.push((i, i));
-            }
-            for i in 1..8 {
-                deltas.push((-i, 0));
-                deltas.push((0, -i));
-                deltas.push((0, i));
-                deltas.push((i, 0));
-            }
-
-            deltas
-        }
-        PT::King { .. } => {
-            vec![
-                (-1, -1),
-                (-1, 0),
-                (-1, 1),
-                (0, -1),
-                (0, 1),
-                (1, -1),
-                (1, 0),
-                (1, 1),
-                (0, 2),
-                (0, -2),
-            ]
-        }
-    };
+pub fn validate_action(state: &State, action: &Action) -> bool {
+    actions(state).contains(action)
+}
 
-    deltas
-        .iter()
-        .map(|(dy, dx)| (s_y as isize + dy, s_x as isize + dx))
-        .filter_map(|(sy, sx)| within_bounds(sy, sx))
+pub fn actions_for_location(state: &State, s_y: usize, s_x: usize) -> Vec<Action> {
+    actions(state)
+        .into_iter()
+        .filter(|a| *a != Action::Tie && a.get_main_coords() == (s_y, s_x))
         .collect()
 }
 
-pub fn actions_for_location(state: &State, s_y: usize, s_x: usize) -> Vec<Action> {
-    let mut found = vec![];
-    for e_y in 0..8 {
-        for e_x in 0..8 {
-            if let Some(action) =
-                Action::from_context_and_coords(state, (s_y, s_x), (e_y, e_x), None)
-            {
-                if validate_action(state, &action) {
-                    found.push(action);
-                }
-            }
-        }
+// A draw can be claimed on threefold repetition or under the fifty-move rule.
+fn can_claim_tie(state: &State) -> bool {
+    if state.halfmove_clock >= FIFTY_MOVE_RULE_PLIES {
+        return true;
     }
 
-    let possible_promotion_types = vec![
-        PT::Queen,
-        PT::Rook { has_moved: true },
-        PT::Bishop,
-        PT::Knight,
-    ];
-
-    for pt in possible_promotion_types {
-        for e_y in [0, 7].iter() {
-            for d_x in -1..=1 {
-                let e_x = s_x as isize + d_x;
-                match e_x.try_into().ok() {
-                    None => (),
-                    Some(e_x) => {
-                        if e_x < 8 {
-                            let current_action = Action::Promotion {
-                                s_y,
-                                s_x,
-                                e_y: *e_y,
-                                e_x,
-                                to_piece: pt,
-                            };
-                            if validate_action(state, &current_action) {
-                                found.push(current_action);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    match state.hash_to_occurences.get(&state.hash()) {
+        None => false,
+        Some(&x) => x >= 3,
     }
-
-    found
 }
 
 pub fn actions(state: &State) -> Vec<Action> {
-    let mut found = vec![];
-    for s_y in 0..8 {
-        for s_x in 0..8 {
-            let piece = match state.board[s_y][s_x] {
-                Some(p) => {
-                    if p.c != state.turn {
-                        continue;
-                    }
-                    p
-                }
-                None => {
-                    continue;
-                }
-            };
+    let mut found = pseudo_legal_actions(state);
+    found.retain(|a| leaves_king_safe(state, a));
 
-            for (e_y, e_x) in interesting_locations(s_y, s_x, &piece) {
-                if let Some(action) =
-                    Action::from_context_and_coords(state, (s_y, s_x), (e_y, e_x), None)
-                {
-                    if validate_action(state, &action) {
-                        found.push(action);
-                    }
-                }
-            }
-        }
+    // Checkmate and stalemate end the game before any draw could be claimed.
+    if !found.is_empty() && can_claim_tie(state) {
+        found.push(Action::Tie);
     }
 
-    let possible_promotion_types = vec![
-        PT::Queen,
-        PT::Rook { has_moved: true },
-        PT::Bishop,
-        PT::Knight,
-    ];
-
-    for s_x in 0..8 {
-        for pt in &possible_promotion_types {
-            for (e_y, s_y) in [(0, 1), (6, 7)].iter() {
-                for d_x in -1..=1 {
-                    let e_x = s_x as isize + d_x;
-                    match e_x.try_into().ok() {
-                        None => (),
-                        Some(e_x) => {
-                            if e_x < 8 {
-                                let current_action = Action::Promotion {
-                                    s_y: *s_y,
-                                    s_x,
-                                    e_y: *e_y,
-                                    e_x,
-                                    to_piece: *pt,
-                                };
-                                if validate_action(state, &current_action) {
-                                    found.push(current_action);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
+    found
+}
 
-    match state.hash_to_occurences.get(&state.hash()) {
-        None => (),
-        Some(&x) => {
-            if x >= 3 {
-                found.push(Action::Tie);
-            }
-        }
-    }
+// Legal captures and promotions, the moves quiescence search keeps playing once the main search
+// runs out of depth.
+pub fn tactical_actions(state: &State) -> Vec<Action> {
+    let mut found = pseudo_legal_actions(state);
+    found.retain(|a| {
+        matches!(
+            a,
+            Action::Capture { .. } | Action::Enpassant { .. } | Action::Promotion { .. }
+        ) && leaves_king_safe(state, a)
+    });
 
     found
 }
 
+// Whether any move can be made on the board. Draw claims are not counted, since they cannot save a
+// player who is checkmated or stalemated.
 pub fn any_actions(state: &State) -> bool {
-    for s_y in 0..8 {
-        for s_x in 0..8 {
-            let piece = match state.board[s_y][s_x] {
-                Some(p) => {
-                    if p.c != state.turn {
-                        continue;
-                    }
-                    p
-                }
-                None => {
-                    continue;
-                }
-            };
-
-            for (e_y, e_x) in interesting_locations(s_y, s_x, &piece) {
-                if let Some(action) =
-                    Action::from_context_and_coords(state, (s_y, s_x), (e_y, e_x), None)
-                {
-                    if validate_action(state, &action) {
-                        return true;
-                    }
-                }
-            }
-        }
-    }
-
-    let possible_promotion_types = vec![
-        PT::Queen,
-        PT::Rook { has_moved: true },
-        PT::Bishop,
-        PT::Knight,
-    ];
-
-    for s_x in 0..8 {
-        for pt in &possible_promotion_types {
-            for (e_y, s_y) in [(0, 1), (6, 7)].iter() {
-                for d_x in -1..=1 {
-                    let e_x = s_x as isize + d_x;
-                    match e_x.try_into().ok() {
-                        None => (),
-                        Some(e_x) => {
-                            if e_x < 8 {
-                                let current_action = Action::Promotion {
-                                    s_y: *s_y,
-                                    s_x,
-                                    e_y: *e_y,
-                                    e_x,
-                                    to_piece: *pt,
-                                };
-                                if validate_action(state, &current_action) {
-                                    return true;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    match state.hash_to_occurences.get(&state.hash()) {
-        None => (),
-        Some(&x) => {
-            if x >= 3 {
-                return true;
-            }
-        }
-    }
-
-    false
+    pseudo_legal_actions(state)
+        .iter()
+        .any(|a| leaves_king_safe(state, a))
 }
@@ -1,173 +1,1024 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
-use std::time::Instant;
-
-use fxhash::FxHashMap;
-
-use core::cmp::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::action::Action;
-use crate::actions::actions;
-use crate::result::{inplace_result, inplace_revert};
+use crate::actions::{actions, is_king_attacked, tactical_actions, validate_action};
+use crate::bitboard::{BISHOP, KNIGHT, QUEEN, ROOK};
+use crate::book::OpeningBook;
+use crate::ordering::{is_quiet, MoveOrdering};
+use crate::result::{
+    inplace_null_move, inplace_null_revert, inplace_result, inplace_revert, result,
+};
+use crate::san::line_to_san;
+use crate::see::see;
 use crate::state::{State, PC};
-use crate::value::{heuristic, heuristic_action, value, Status};
+use crate::syzygy::{Tablebase, Wdl};
+use crate::time_manager::TimeBudget;
+use crate::tt::{Bound, TranspositionTable};
+use crate::value::{heuristic, heuristic_action, value, GameResult};
+
+// Utilities are in centipawns, for the side to move. Checkmating is worth MATE minus the number of
+// plies from the root to the mated position, so that the search prefers the quickest mate and the
+// slowest defeat.
+pub const MATE: i32 = 30000;
+// No mate is found further than this many plies from the root, so any utility at least
+// MATE - MAX_PLY away from zero is a mate.
+const MAX_PLY: i32 = 1000;
+pub const INF: i32 = MATE + 1;
+const TIMEOUT: i32 = i32::MAX;
+// A capture is skipped in quiescence search when even winning this much more than the captured
+// piece would not bring the score up to alpha.
+const DELTA_MARGIN: i32 = 200;
+// Number of negamax nodes between two checks of whether the search has to stop.
+const STOP_POLL_INTERVAL: u32 = 1024;
+// Width of the windows used to test whether a utility is above or below a bound.
+const NULL_WINDOW: i32 = 1;
+// Null-move pruning searches at least this many plies.
+const NULL_MOVE_MIN_DEPTH: u32 = 3;
+const REVERSE_FUTILITY_DEPTH: u32 = 3;
+// Margin per ply of depth left that the heuristic has to clear for reverse futility pruning.
+const REVERSE_FUTILITY_MARGIN: i32 = 120;
+const FUTILITY_DEPTH: u32 = 2;
+// How much a quiet move is assumed to gain at most, per ply of depth left.
+const FUTILITY_MARGIN: i32 = 150;
+// Late-move reductions start with this move, and only with this much depth left.
+const LMR_MIN_MOVES: usize = 4;
+const LMR_MIN_DEPTH: u32 = 3;
+// Utility of a position the tablebases know to be won, below that of any mate the search finds.
+pub const TB_WIN: i32 = 20000;
+pub const DEFAULT_CONTEMPT: i32 = 20;
+
+// Where the move of a SearchInfo comes from. Moves that are not searched have no depth, score or
+// nodes of their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveSource {
+    Search,
+    Book,
+    Tablebase,
+}
+
+impl fmt::Display for MoveSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MoveSource::Search => "search",
+            MoveSource::Book => "book",
+            MoveSource::Tablebase => "tablebase",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The selective parts of the search, which trade a little accuracy for depth. Each can be switched
+// off, to measure what it is worth in games between engines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility: bool,
+    pub futility: bool,
+    pub check_extensions: bool,
+    // Number of threads searching at the same time, including the one reporting the result.
+    pub threads: usize,
+    // How much worse than an even position a draw is for the side the search is for, in
+    // centipawns. Negative values make the engine seek draws.
+    pub contempt: i32,
+}
+
+impl SearchOptions {
+    // Names of the switches in the engine protocols, in the order of the fields.
+    pub const SWITCHES: [&'static str; 5] = [
+        "Null Move Pruning",
+        "Late Move Reductions",
+        "Reverse Futility Pruning",
+        "Futility Pruning",
+        "Check Extensions",
+    ];
+
+    pub fn switch_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let i = SearchOptions::SWITCHES
+            .iter()
+            .position(|switch| switch.eq_ignore_ascii_case(name))?;
+        Some(match i {
+            0 => &mut self.null_move,
+            1 => &mut self.late_move_reductions,
+            2 => &mut self.reverse_futility,
+            3 => &mut self.futility,
+            _ => &mut self.check_extensions,
+        })
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            null_move: true,
+            late_move_reductions: true,
+            reverse_futility: true,
+            futility: true,
+            check_extensions: true,
+            threads: 1,
+            contempt: DEFAULT_CONTEMPT,
+        }
+    }
+}
+
+// Progress report sent after every completed iteration of ai_move.
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub source: MoveSource,
+    pub depth: u32,
+    pub utility: i32,
+    pub action: Action,
+    // The best move on the board, which is action unless that claims a draw. Engine protocols
+    // without draw claims play this instead.
+    pub board_action: Action,
+    // The line the search expects to be played, starting with action.
+    pub pv: Vec<Action>,
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
 
-const INF: f64 = 1e+9;
-const TIMEOUT: f64 = 2e+9;
+impl SearchInfo {
+    // The number of moves until the side to move mates, negative if it gets mated, or None if
+    // the search found no forced mate.
+    pub fn mate_in(&self) -> Option<i32> {
+        if self.utility >= MATE - MAX_PLY {
+            Some((MATE - self.utility + 1) / 2)
+        } else if self.utility <= MAX_PLY - MATE {
+            Some(-(MATE + self.utility) / 2)
+        } else {
+            None
+        }
+    }
+}
+
+// Decides when a search has to be abandoned: when asked to through the done channel, once the
+// hard time limit has passed, or once another thread of the same search has stopped. The channel
+// and the clock are only polled every STOP_POLL_INTERVAL nodes, since reading the clock is
+// comparatively slow, which is also when the nodes searched so far are added to the count shared
+// by all threads.
+struct StopCondition<'a> {
+    // Only the main thread listens to the channel and keeps time, and only once it has a move.
+    done_channel: Option<&'a Receiver<()>>,
+    deadline: Option<Instant>,
+    stopped: &'a AtomicBool,
+    total_nodes: &'a AtomicU64,
+    counted_nodes: u64,
+    nodes_until_poll: u32,
+}
 
-fn minimax(
+impl<'a> StopCondition<'a> {
+    fn new(stopped: &'a AtomicBool, total_nodes: &'a AtomicU64) -> Self {
+        StopCondition {
+            done_channel: None,
+            deadline: None,
+            stopped,
+            total_nodes,
+            counted_nodes: 0,
+            nodes_until_poll: STOP_POLL_INTERVAL,
+        }
+    }
+
+    fn should_stop(&mut self, nodes: u64) -> bool {
+        if self.stopped.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.nodes_until_poll > 0 {
+            self.nodes_until_poll -= 1;
+            return false;
+        }
+        self.nodes_until_poll = STOP_POLL_INTERVAL;
+        self.count_nodes(nodes);
+
+        let stop = self
+            .done_channel
+            .is_some_and(|done_channel| done_channel.try_recv().is_ok())
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        if stop {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+        stop
+    }
+
+    // Adds the nodes this thread searched since the last call to the shared count, and returns
+    // the count.
+    fn count_nodes(&mut self, nodes: u64) -> u64 {
+        let new_nodes = nodes - self.counted_nodes;
+        self.counted_nodes = nodes;
+        self.total_nodes.fetch_add(new_nodes, Ordering::Relaxed) + new_nodes
+    }
+}
+
+// Recovers the principal variation by following the best actions stored in the transposition
+// table, starting from the position after best_action. The walk stops at a position without a
+// usable stored action and after a repetition, which would otherwise make it loop.
+fn principal_variation(
+    state: &State,
+    best_action: Action,
+    max_length: u32,
+    tt: &TranspositionTable,
+) -> Vec<Action> {
+    let mut pv = vec![best_action];
+    let mut state = result(state, &best_action);
+    let mut seen = vec![state.hash()];
+
+    while pv.len() < max_length as usize && best_action != Action::Tie {
+        let action = match tt.probe(state.hash()).and_then(|entry| entry.action) {
+            Some(action) if action != Action::Tie && validate_action(&state, &action) => action,
+            _ => break,
+        };
+        pv.push(action);
+        state = result(&state, &action);
+
+        if seen.contains(&state.hash()) {
+            break;
+        }
+        seen.push(state.hash());
+    }
+
+    pv
+}
+
+// Keeps playing captures and promotions from a leaf of negamax until the position is quiet, so that
+// the search does not stop in the middle of an exchange. The side to move may stand pat on
+// heuristic_cache instead of capturing, unless it is in check, in which case every evasion is
+// searched. Returns TIMEOUT once the search has to stop, like negamax.
+fn quiescence(
     state: &mut State,
-    depth_left: u32,
-    mut alpha: f64,
-    mut beta: f64,
-    heuristic_cache: f64,
-    done_channel: &mut Receiver<()>,
-    move_cache: &mut FxHashMap<u64, (u32, f64, Option<Action>)>,
-) -> (f64, Option<Action>) {
-    let (mut best_utility, mut best_action) = (-2. * INF, None);
-
-    if depth_left >= 5 {
-        if let Ok(()) = done_channel.try_recv() {
-            return (TIMEOUT, None);
+    ply: u32,
+    mut alpha: i32,
+    beta: i32,
+    heuristic_cache: i32,
+    search: &mut Search,
+) -> i32 {
+    search.nodes += 1;
+    if search.stop.should_stop(search.nodes) {
+        return TIMEOUT;
+    }
+
+    let in_check = is_king_attacked(state, state.turn);
+    let mut current_actions = if in_check {
+        let evasions = actions(state);
+        if !evasions.iter().any(|a| *a != Action::Tie) {
+            return ply as i32 - MATE;
+        }
+        evasions
+    } else {
+        if heuristic_cache >= beta {
+            return heuristic_cache;
+        }
+        alpha = i32::max(alpha, heuristic_cache);
+        tactical_actions(state)
+    };
+    current_actions.retain(|a| *a != Action::Tie);
+
+    let mut action_heuristics: Vec<(Action, i32)> = current_actions
+        .into_iter()
+        .map(|a| (a, heuristic_action(state, &a)))
+        .collect();
+    action_heuristics.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let mut best_utility = if in_check { -INF } else { heuristic_cache };
+    for (a, action_heuristic) in action_heuristics {
+        if !in_check && heuristic_cache + action_heuristic + DELTA_MARGIN <= alpha {
+            // the remaining actions gain even less.
+            break;
+        }
+        // Captures that lose material once the exchange is played out are not searched either.
+        if !in_check && see(state, &a) < 0 {
+            continue;
+        }
+
+        let moved_pieces = inplace_result(state, &a);
+        let response_utility = quiescence(
+            state,
+            ply + 1,
+            -beta,
+            -alpha,
+            -(heuristic_cache + action_heuristic),
+            search,
+        );
+        inplace_revert(state, moved_pieces);
+        if response_utility == TIMEOUT {
+            return TIMEOUT;
+        }
+        let response_utility = -response_utility;
+
+        best_utility = i32::max(best_utility, response_utility);
+        alpha = i32::max(alpha, response_utility);
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    best_utility
+}
+
+// Utility of a draw for the side to move at ply. The side the search is for moves at even plies.
+fn draw_utility(ply: u32, contempt: i32) -> i32 {
+    if ply.is_multiple_of(2) {
+        -contempt
+    } else {
+        contempt
+    }
+}
+
+// Tablebase outcomes for the side to move. Cursed wins and blessed losses are draws under the
+// fifty-move rule, but still better or worse than a plain draw.
+fn wdl_utility(wdl: Wdl, draw: i32) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN,
+        Wdl::CursedWin => draw + 1,
+        Wdl::Draw => draw,
+        Wdl::BlessedLoss => draw - 1,
+        Wdl::Loss => -TB_WIN,
+    }
+}
+
+// Mate utilities count the plies from the root, but a position can be reached at different plies,
+// so the transposition table stores them counted from the position itself instead.
+fn utility_to_tt(utility: i32, ply: u32) -> i32 {
+    if utility >= MATE - MAX_PLY {
+        utility + ply as i32
+    } else if utility <= MAX_PLY - MATE {
+        utility - ply as i32
+    } else {
+        utility
+    }
+}
+
+fn utility_from_tt(utility: i32, ply: u32) -> i32 {
+    if utility >= MATE - MAX_PLY {
+        utility - ply as i32
+    } else if utility <= MAX_PLY - MATE {
+        utility + ply as i32
+    } else {
+        utility
+    }
+}
+
+fn has_non_pawn_material(state: &State, c: PC) -> bool {
+    [KNIGHT, BISHOP, ROOK, QUEEN]
+        .iter()
+        .any(|t| state.bitboards.of(c, *t) != 0)
+}
+
+// Everything negamax needs besides the position, for the whole of one ai_move call. Every thread
+// of the search has its own, sharing only the transposition table and the stop condition.
+struct Search<'a> {
+    stop: StopCondition<'a>,
+    tt: &'a TranspositionTable,
+    tablebase: Option<&'a Tablebase>,
+    options: SearchOptions,
+    // Depth of the current iteration.
+    root_depth: u32,
+    // Set for the reply to a null move, so that two null moves are never played in a row.
+    after_null_move: bool,
+    // The move that led to the current node, or None after a null move and at the root.
+    previous_action: Option<Action>,
+    // Hashes of the positions on the current line, indexed by ply.
+    path: Vec<u64>,
+    // Ply of the position after the latest null move on the current line, or 0 without one.
+    // Positions before it are not part of the same game.
+    null_move_ply: u32,
+    // The best action at the root other than claiming a draw, in the current iteration.
+    root_board_action: Option<Action>,
+    ordering: MoveOrdering,
+    nodes: u64,
+}
+
+impl<'a> Search<'a> {
+    fn new(
+        stop: StopCondition<'a>,
+        tt: &'a TranspositionTable,
+        tablebase: Option<&'a Tablebase>,
+        options: SearchOptions,
+    ) -> Self {
+        Search {
+            stop,
+            tt,
+            tablebase,
+            options,
+            root_depth: 0,
+            after_null_move: false,
+            previous_action: None,
+            path: Vec::new(),
+            null_move_ply: 0,
+            root_board_action: None,
+            ordering: MoveOrdering::new(),
+            nodes: 0,
+        }
+    }
+
+    // Whether the position at ply repeats one on the current line, the root included, which is
+    // scored as a draw since the side that could avoid the repetition did not. Positions from
+    // before the search only count once they occurred twice, which makes this one the third
+    // occurrence. Captures, pawn moves and null moves cannot be undone, so positions before them
+    // are not looked at.
+    fn is_repetition(&self, state: &State, ply: u32) -> bool {
+        let hash = state.hash();
+        let earliest = (ply as usize)
+            .saturating_sub(state.halfmove_clock)
+            .max(self.null_move_ply as usize);
+        let in_line = (earliest..(ply as usize).saturating_sub(3))
+            .rev()
+            .step_by(2)
+            .any(|i| self.path[i] == hash);
+        // The counts include both the game before the search and the current line.
+        let in_game =
+            self.null_move_ply == 0 && state.hash_to_occurences.get(&hash).is_some_and(|&n| n >= 3);
+
+        in_line || in_game
+    }
+}
+
+// Searches state to depth_left plies and returns its utility for the side to move, together with
+// the best action. A utility at or below alpha only means that the real one is no higher, and one
+// at or above beta that it is no lower.
+fn negamax(
+    state: &mut State,
+    mut depth_left: u32,
+    ply: u32,
+    mut alpha: i32,
+    mut beta: i32,
+    heuristic_cache: i32,
+    search: &mut Search,
+) -> (i32, Option<Action>) {
+    let (mut best_utility, mut best_action) = (-INF, None);
+    search.nodes += 1;
+    let after_null_move = std::mem::replace(&mut search.after_null_move, false);
+    let previous_action = search.previous_action.take();
+
+    if search.stop.should_stop(search.nodes) {
+        return (TIMEOUT, None);
+    }
+
+    search.path.truncate(ply as usize);
+    search.path.push(state.hash());
+    if ply > 0 && search.is_repetition(state, ply) {
+        return (draw_utility(ply, search.options.contempt), None);
+    }
+
+    // Nothing found below this node can beat mating right now or being mated right now, so a
+    // shorter mate found elsewhere makes searching it pointless.
+    if ply > 0 {
+        alpha = i32::max(alpha, ply as i32 - MATE);
+        beta = i32::min(beta, MATE - ply as i32 - 1);
+        if alpha >= beta {
+            return (alpha, None);
         }
     }
 
+    let original_alpha = alpha;
     let mut cache_best_action = None;
 
     if depth_left >= 1 {
-        if let Some((cache_depth_left, cache_value, cache_action)) = move_cache.get(&state.hash()) {
-            if cache_depth_left >= &depth_left {
-                return (*cache_value, None);
+        if let Some(entry) = search.tt.probe(state.hash()) {
+            if entry.depth >= depth_left {
+                let utility = utility_from_tt(entry.utility, ply);
+                let usable = match entry.bound {
+                    Bound::Exact => true,
+                    Bound::Lower => utility >= beta,
+                    Bound::Upper => utility <= alpha,
+                };
+                // The root has to search its moves, since a stored action may come from another
+                // thread or an earlier search that reached the position along a different line.
+                if usable && ply > 0 {
+                    return (utility, entry.action);
+                }
             }
-            cache_best_action = *cache_action;
+            cache_best_action = entry.action;
         }
     }
 
     match value(state) {
-        Status::Running => (),
-        Status::BlackWin => {
+        GameResult::Running => (),
+        GameResult::BlackWin(_) => {
             if state.turn == PC::Black {
-                return (INF, None);
+                return (MATE - ply as i32, None);
             } else {
-                return (-INF, None);
+                return (ply as i32 - MATE, None);
             }
         }
-        Status::WhiteWin => {
+        GameResult::WhiteWin(_) => {
             if state.turn == PC::White {
-                return (INF, None);
+                return (MATE - ply as i32, None);
             } else {
-                return (-INF, None);
+                return (ply as i32 - MATE, None);
             }
         }
-        Status::Tie => {
-            return (0., None);
+        // A drawn root position still gets a move, since the caller needs one.
+        GameResult::Tie(_) if ply > 0 => {
+            return (draw_utility(ply, search.options.contempt), None);
         }
+        GameResult::Tie(_) => (),
     }
 
-    if depth_left == 0 {
-        return (heuristic_cache, None);
-    }
-
-    let mut current_actions = actions(state);
-    current_actions.sort_by(|a, b| {
-        // compare so that array is in descending order
-        let heuristic_comparison = heuristic_action(state, b)
-            .partial_cmp(&heuristic_action(state, a))
-            .unwrap();
-
-        match &cache_best_action {
-            Some(cache_action) => {
-                if a == cache_action && b == cache_action {
-                    Ordering::Equal
-                } else if a == cache_action {
-                    Ordering::Less
-                } else if b == cache_action {
-                    Ordering::Greater
-                } else {
-                    heuristic_comparison
-                }
-            }
-            _ => heuristic_comparison,
+    // Tablebase outcomes assume that the fifty-move counter was just reset. The root is left to
+    // ai_move, which needs a move rather than an outcome.
+    if ply > 0 && state.halfmove_clock == 0 {
+        if let Some(wdl) = search.tablebase.and_then(|tb| tb.probe_wdl(state)) {
+            let draw = draw_utility(ply, search.options.contempt);
+            return (wdl_utility(wdl, draw), None);
         }
-    });
+    }
 
-    for a in current_actions {
-        let action_heuristic = heuristic_action(state, &a);
+    let turn = state.turn;
+    let in_check = is_king_attacked(state, turn);
+    // Positions in check are searched one ply deeper, so that forcing lines do not end at the
+    // horizon. The limit keeps long series of checks from extending the search without end.
+    if in_check && search.options.check_extensions && ply < 2 * search.root_depth {
+        depth_left += 1;
+    }
 
-        let moved_pieces = inplace_result(state, &a);
+    if depth_left == 0 {
+        return (
+            quiescence(state, ply, alpha, beta, heuristic_cache, search),
+            None,
+        );
+    }
+
+    // Near the leaves, a position whose heuristic is well above beta is assumed to stay there.
+    // The heuristic says nothing about a king in check or about mates.
+    let can_prune = ply > 0 && !in_check && beta.abs() < TB_WIN;
+    if can_prune
+        && search.options.reverse_futility
+        && depth_left <= REVERSE_FUTILITY_DEPTH
+        && heuristic_cache - REVERSE_FUTILITY_MARGIN * depth_left as i32 >= beta
+    {
+        return (
+            heuristic_cache - REVERSE_FUTILITY_MARGIN * depth_left as i32,
+            None,
+        );
+    }
 
-        let (mut response_utility, _) = minimax(
+    // If passing the turn still leaves the side to move at or above beta after a shallower
+    // search, a real move almost certainly does too. This fails in zugzwang, where any move
+    // makes things worse, which mostly happens to a side with only pawns left.
+    if can_prune
+        && search.options.null_move
+        && !after_null_move
+        && depth_left >= NULL_MOVE_MIN_DEPTH
+        && heuristic_cache >= beta
+        && has_non_pawn_material(state, turn)
+    {
+        let reduction = if depth_left > 6 { 3 } else { 2 };
+        let undo = inplace_null_move(state);
+        search.after_null_move = true;
+        let null_move_ply = std::mem::replace(&mut search.null_move_ply, ply + 1);
+        let (response_utility, _) = negamax(
             state,
-            depth_left - 1,
-            alpha,
-            beta,
-            -(heuristic_cache + action_heuristic),
-            done_channel,
-            move_cache,
+            depth_left.saturating_sub(1 + reduction),
+            ply + 1,
+            -beta,
+            -beta + NULL_WINDOW,
+            -heuristic_cache,
+            search,
         );
+        inplace_null_revert(state, undo);
+        search.null_move_ply = null_move_ply;
         if response_utility == TIMEOUT {
-            return (TIMEOUT, best_action);
+            return (TIMEOUT, None);
+        }
+        if -response_utility >= beta {
+            return (beta, None);
         }
-        response_utility *= -1.;
+    }
+
+    // Quiet moves cannot bring a position whose heuristic is well below alpha back up, unless
+    // they give check.
+    let futile = ply > 0
+        && !in_check
+        && search.options.futility
+        && depth_left <= FUTILITY_DEPTH
+        && heuristic_cache + FUTILITY_MARGIN * depth_left as i32 <= alpha;
+
+    let mut current_actions = search.ordering.order(
+        state,
+        actions(state),
+        cache_best_action,
+        ply,
+        previous_action,
+    );
+    // A draw claim is searched last at the root, so that the moves before it are compared with
+    // each other rather than with the claim, and the best of them is known as well.
+    if ply == 0 {
+        if let Some(i) = current_actions.iter().position(|(a, _)| *a == Action::Tie) {
+            let claim = current_actions.remove(i);
+            current_actions.push(claim);
+        }
+    }
+    // Quiet moves searched so far, whose history is lowered if a later quiet move causes a cutoff.
+    let mut tried_quiets = Vec::new();
+
+    for (i, (a, action_heuristic)) in current_actions.into_iter().enumerate() {
+        let quiet = is_quiet(&a);
+
+        let moved_pieces = inplace_result(state, &a);
+        let gives_check = is_king_attacked(state, state.turn);
+
+        if futile && quiet && !gives_check {
+            inplace_revert(state, moved_pieces);
+            best_utility = i32::max(
+                best_utility,
+                heuristic_cache + FUTILITY_MARGIN * depth_left as i32,
+            );
+            continue;
+        }
+
+        // Moves late in the ordering rarely turn out best, so quiet ones are first searched less
+        // deeply and only to see whether they beat alpha, and searched again in full if they do.
+        let mut reduced_utility = None;
+        if search.options.late_move_reductions
+            && i >= LMR_MIN_MOVES
+            && depth_left >= LMR_MIN_DEPTH
+            && quiet
+            && !in_check
+            && !gives_check
+        {
+            let reduction = if i >= 2 * LMR_MIN_MOVES && depth_left >= 5 {
+                2
+            } else {
+                1
+            };
+            search.previous_action = Some(a);
+            let (response_utility, _) = negamax(
+                state,
+                depth_left - 1 - reduction,
+                ply + 1,
+                -alpha - NULL_WINDOW,
+                -alpha,
+                -(heuristic_cache + action_heuristic),
+                search,
+            );
+            if response_utility == TIMEOUT {
+                return (TIMEOUT, best_action);
+            }
+            if -response_utility <= alpha {
+                reduced_utility = Some(-response_utility);
+            }
+        }
+
+        let response_utility = match reduced_utility {
+            Some(utility) => utility,
+            None => {
+                search.previous_action = Some(a);
+                let (response_utility, _) = negamax(
+                    state,
+                    depth_left - 1,
+                    ply + 1,
+                    -beta,
+                    -alpha,
+                    -(heuristic_cache + action_heuristic),
+                    search,
+                );
+                if response_utility == TIMEOUT {
+                    return (TIMEOUT, best_action);
+                }
+                -response_utility
+            }
+        };
         if best_utility < response_utility {
             best_utility = response_utility;
             best_action = Some(a);
+            if ply == 0 && a != Action::Tie {
+                search.root_board_action = Some(a);
+            }
         }
 
         inplace_revert(state, moved_pieces);
 
-        if state.turn == PC::White {
-            alpha = f64::max(alpha, response_utility);
-        } else {
-            beta = f64::min(beta, -response_utility);
-        }
-
+        alpha = i32::max(alpha, response_utility);
         if alpha >= beta {
+            if quiet {
+                search.ordering.record_cutoff(
+                    state,
+                    a,
+                    &tried_quiets,
+                    depth_left,
+                    ply,
+                    previous_action,
+                );
+            }
             break;
         }
+        if quiet {
+            tried_quiets.push(a);
+        }
     }
 
-    if depth_left >= 1 {
-        move_cache.insert(state.hash(), (depth_left, best_utility, best_action));
-    }
+    let bound = if best_utility <= original_alpha {
+        Bound::Upper
+    } else if best_utility >= beta {
+        Bound::Lower
+    } else {
+        Bound::Exact
+    };
+    search.tt.store(
+        state.hash(),
+        depth_left,
+        utility_to_tt(best_utility, ply),
+        bound,
+        best_action,
+    );
 
     (best_utility, best_action)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn ai_move(
     mut state: State,
-    tx: Sender<(u32, Action)>,
+    tx: Sender<SearchInfo>,
     max_depth: u32,
-    mut done_channel: Receiver<()>,
-    move_cache: &mut FxHashMap<u64, (u32, f64, Option<Action>)>,
+    time_budget: Option<TimeBudget>,
+    done_channel: Receiver<()>,
+    book: Option<&OpeningBook>,
+    tablebase: Option<&Tablebase>,
+    options: SearchOptions,
+    tt: &TranspositionTable,
 ) {
     let start = Instant::now();
+    // A book move is played without searching.
+    if let Some(action) = book.and_then(|book| book.probe(&state)) {
+        log::info!("AI move: {} from the opening book", action.to_san(&state));
+        let _ = tx.send(SearchInfo {
+            source: MoveSource::Book,
+            depth: 0,
+            utility: 0,
+            action,
+            board_action: action,
+            pv: vec![action],
+            nodes: 0,
+            elapsed: start.elapsed(),
+        });
+        return;
+    }
+
+    // So is a tablebase move, unless the position is drawn and the search has to pick a move that
+    // keeps the draw.
+    if let Some((action, wdl)) = tablebase.and_then(|tb| tb.best_move(&mut state)) {
+        log::info!(
+            "AI move: {} from the tablebases, a {}",
+            action.to_san(&state),
+            wdl
+        );
+        let _ = tx.send(SearchInfo {
+            source: MoveSource::Tablebase,
+            depth: 0,
+            utility: wdl_utility(wdl, draw_utility(0, options.contempt)),
+            action,
+            board_action: action,
+            pv: vec![action],
+            nodes: 0,
+            elapsed: start.elapsed(),
+        });
+        return;
+    }
+
+    let root_draw = draw_utility(0, options.contempt);
+    tt.new_search(if state.turn == PC::White {
+        root_draw
+    } else {
+        -root_draw
+    });
+    let stopped = AtomicBool::new(false);
+    let total_nodes = AtomicU64::new(0);
+    thread::scope(|scope| {
+        for id in 1..options.threads {
+            let state = state.clone();
+            let (stopped, total_nodes) = (&stopped, &total_nodes);
+            scope.spawn(move || {
+                let stop = StopCondition::new(stopped, total_nodes);
+                helper_search(
+                    state,
+                    id,
+                    max_depth,
+                    &mut Search::new(stop, tt, tablebase, options),
+                );
+            });
+        }
+
+        let stop = StopCondition::new(&stopped, &total_nodes);
+        let mut search = Search::new(stop, tt, tablebase, options);
+        main_search(
+            state,
+            &tx,
+            max_depth,
+            time_budget,
+            &done_channel,
+            start,
+            &mut search,
+        );
+        stopped.store(true, Ordering::Relaxed);
+    });
+}
+
+// A move to play when a search did not get to report one: the one stored for state in the
+// transposition table, or else the first legal one. Draws are never claimed.
+pub fn fallback_action(state: &State, tt: &TranspositionTable) -> Option<Action> {
+    tt.probe(state.hash())
+        .and_then(|entry| entry.action)
+        .filter(|action| *action != Action::Tie && validate_action(state, action))
+        .or_else(|| {
+            actions(state)
+                .into_iter()
+                .find(|action| *action != Action::Tie)
+        })
+}
+
+// Lazy SMP: helper threads search the same position as the main thread and only help it by
+// filling the transposition table. Every other helper starts one ply deeper, so that the threads
+// do not all search the same tree in the same order.
+fn helper_search(mut state: State, id: usize, max_depth: u32, search: &mut Search) {
+    for depth in (1 + id as u32 % 2)..=max_depth {
+        search.root_depth = depth;
+        let curr_h = heuristic(&state);
+        let (utility, _) = negamax(&mut state, depth, 0, -INF, INF, curr_h, search);
+        if utility == TIMEOUT {
+            break;
+        }
+    }
+    search.stop.count_nodes(search.nodes);
+}
+
+// Iterative deepening on the main thread, which reports every completed iteration.
+fn main_search<'a>(
+    mut state: State,
+    tx: &Sender<SearchInfo>,
+    max_depth: u32,
+    time_budget: Option<TimeBudget>,
+    done_channel: &'a Receiver<()>,
+    start: Instant,
+    search: &mut Search<'a>,
+) {
+    let mut last_iteration = Duration::ZERO;
     for depth in 1..=max_depth {
+        // The first iteration always finishes, so that there is a move to play.
+        if depth > 1 {
+            if let Some(budget) = time_budget {
+                if !budget.can_start_iteration(start.elapsed(), last_iteration) {
+                    break;
+                }
+                search.stop.deadline = Some(start + budget.hard);
+            }
+            search.stop.done_channel = Some(done_channel);
+        }
+
+        let iteration_start = Instant::now();
         let curr_h = heuristic(&state);
-        let (best_utility, best_action) = minimax(
-            &mut state,
+        search.root_depth = depth;
+        search.root_board_action = None;
+        let (best_utility, best_action) = negamax(&mut state, depth, 0, -INF, INF, curr_h, search);
+        last_iteration = iteration_start.elapsed();
+
+        if best_utility == TIMEOUT {
+            return;
+        }
+
+        let best_action = best_action.expect("No move available for AI");
+        let pv = principal_variation(&state, best_action, depth, search.tt);
+        log::info!(
+            "AI move: {} at depth {}, with utility {}, expecting {}",
+            best_action.to_san(&state),
             depth,
-            -INF,
-            INF,
-            curr_h,
-            &mut done_channel,
-            move_cache,
+            best_utility,
+            line_to_san(&state, &pv)
         );
+        let _ = tx.send(SearchInfo {
+            source: MoveSource::Search,
+            depth,
+            utility: best_utility,
+            action: best_action,
+            board_action: search.root_board_action.unwrap_or(best_action),
+            pv,
+            nodes: search.stop.count_nodes(search.nodes),
+            elapsed: start.elapsed(),
+        });
 
+        // negamax only polls for the stop signal now and then, so it may have arrived after the
+        // iteration was as good as finished. The finished iteration still counts.
         if done_channel.try_recv().is_ok() {
             return;
         }
+    }
+    let duration = start.elapsed();
+    log::info!("Time elapsed for move is: {:?}", duration);
+}
 
-        if best_utility != TIMEOUT {
-            let best_action = best_action.expect("No move available for AI");
-            println!(
-                "AI move: {} at depth {}, with utility {:.2}",
-                best_action.to_string(&state),
-                depth,
-                best_utility
-            );
-            let _ = tx.send((depth, best_action));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn search_utility(fen: &str, depth: u32, contempt: i32, tt: &TranspositionTable) -> i32 {
+        let (tx, rx) = channel();
+        let (_done_tx, done_rx) = channel();
+        let options = SearchOptions {
+            contempt,
+            ..SearchOptions::default()
+        };
+        let state = State::from_fen(fen).unwrap();
+        ai_move(state, tx, depth, None, done_rx, None, None, options, tt);
+        rx.iter().last().unwrap().utility
+    }
+
+    // Plays moves from fen, the first ones before the search and the others on the searched line,
+    // and tells whether the last position counts as a repetition.
+    fn line_repeats(fen: &str, game: &[&str], line: &[&str]) -> bool {
+        let mut state = State::from_fen(fen).unwrap();
+        for uci in game {
+            state = result(&state, &Action::from_uci(&state, uci).unwrap());
+        }
+        let (stopped, total_nodes) = (AtomicBool::new(false), AtomicU64::new(0));
+        let tt = TranspositionTable::new(1);
+        let stop = StopCondition::new(&stopped, &total_nodes);
+        let mut search = Search::new(stop, &tt, None, SearchOptions::default());
+
+        search.path.push(state.hash());
+        for uci in line {
+            let action = Action::from_uci(&state, uci).unwrap();
+            inplace_result(&mut state, &action);
+            search.path.push(state.hash());
+        }
+        search.is_repetition(&state, line.len() as u32)
+    }
+
+    #[test]
+    fn repetitions() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let knights_out = ["g1f3", "g8f6"];
+        let knights_back = ["f3g1", "f6g8"];
+
+        // Twofold on the line, from the root or from later on.
+        assert!(line_repeats(start, &[], &["g1f3", "g8f6", "f3g1", "f6g8"]));
+        assert!(line_repeats(
+            start,
+            &[],
+            &["e2e4", "g8f6", "g1f3", "f6g8", "f3g1"]
+        ));
+        assert!(!line_repeats(start, &[], &["g1f3", "g8f6", "f3g1", "f6h5"]));
+        // A pawn move in between.
+        assert!(!line_repeats(
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            &[],
+            &["e1d1", "e8d8", "d1e1", "d8e8", "e2e3", "e8d8", "e1d1"],
+        ));
+
+        // Threefold with the game before the search.
+        let game = [knights_out, knights_back, knights_out].concat();
+        assert!(line_repeats(start, &game, &knights_back));
+        assert!(!line_repeats(start, &game, &knights_back[..1]));
+        assert!(!line_repeats(start, &knights_out, &knights_back));
+    }
+
+    #[test]
+    fn quiescence_stops() {
+        let mut state =
+            State::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/3PP3/5N2/PPP2PPP/RNBQKB1R b KQkq - 0 3")
+                .unwrap();
+        let (stopped, total_nodes) = (AtomicBool::new(false), AtomicU64::new(0));
+        let tt = TranspositionTable::new(1);
+        let heuristic_cache = heuristic(&state);
+
+        let stop = StopCondition::new(&stopped, &total_nodes);
+        let mut search = Search::new(stop, &tt, None, SearchOptions::default());
+        let utility = quiescence(&mut state, 0, -INF, INF, heuristic_cache, &mut search);
+        assert!(utility.abs() < MATE);
+
+        // A deadline that has passed ends it at the next poll.
+        let mut stop = StopCondition::new(&stopped, &total_nodes);
+        stop.deadline = Some(Instant::now());
+        stop.nodes_until_poll = 0;
+        let mut search = Search::new(stop, &tt, None, SearchOptions::default());
+        let fen = state.to_fen();
+        assert_eq!(
+            quiescence(&mut state, 0, -INF, INF, heuristic_cache, &mut search),
+            TIMEOUT
+        );
+        assert_eq!(state.to_fen(), fen);
+        assert!(stopped.load(Ordering::Relaxed));
+    }
+
+    // With contempt, draws are worth something else to each side, so what a search for one side
+    // left in the table must not change the result of a search for the other.
+    #[test]
+    fn contempt_for_both_colors() {
+        // Black can only hope for the fifty-move rule, which is worth -50 to it.
+        let white = "4k3/8/8/8/8/8/8/RQ2K3 w - - 96 80";
+        let black = "4k3/8/8/8/8/8/8/RQ2K3 b - - 96 80";
+        for depth in 5..=6 {
+            let fresh_white = search_utility(white, depth, 50, &TranspositionTable::new(1));
+            let fresh_black = search_utility(black, depth, 50, &TranspositionTable::new(1));
+
+            let tt = TranspositionTable::new(1);
+            assert_eq!(search_utility(white, depth, 50, &tt), fresh_white);
+            assert_eq!(search_utility(black, depth, 50, &tt), fresh_black);
+            assert_eq!(search_utility(white, depth, 50, &tt), fresh_white);
         }
     }
-    let duration = start.elapsed();
-    println!("Time elapsed for move is: {:?}", duration);
 }
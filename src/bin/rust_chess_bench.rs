@@ -0,0 +1,86 @@
+// Searches a fixed set of positions with more and more threads, to see how the number of nodes
+// searched per second scales.
+// Usage: rust_chess_bench [depth] [max threads]
+use rust_chess::ai::{ai_move, SearchOptions};
+use rust_chess::state::State;
+use rust_chess::tt::{TranspositionTable, DEFAULT_TT_SIZE_MB};
+use std::env;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_DEPTH: u32 = 8;
+
+const POSITIONS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "r2q1rk1/pp2bppp/2n1pn2/3p4/3P4/2NBPN2/PP3PPP/R2Q1RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/5PPP/2R3K1 w - - 0 1",
+];
+
+fn parse_arg<T: std::str::FromStr>(args: &[String], i: usize, default: T) -> T {
+    match args.get(i) {
+        Some(arg) => arg.parse::<T>().unwrap_or_else(|_| {
+            eprintln!("Usage: rust_chess_bench [depth] [max threads]");
+            process::exit(1);
+        }),
+        None => default,
+    }
+}
+
+// Searches every position to depth with a fresh transposition table, returning the nodes searched
+// and the time it took.
+fn bench(depth: u32, threads: usize) -> (u64, Duration) {
+    let options = SearchOptions {
+        threads,
+        ..SearchOptions::default()
+    };
+    let (mut nodes, mut elapsed) = (0, Duration::ZERO);
+    for fen in POSITIONS.iter() {
+        let state = State::from_fen(fen).unwrap();
+        let tt = TranspositionTable::new(DEFAULT_TT_SIZE_MB);
+        let (tx, rx) = mpsc::channel();
+        let (_done_tx, done_rx) = mpsc::channel();
+
+        let start = Instant::now();
+        ai_move(state, tx, depth, None, done_rx, None, None, options, &tt);
+        elapsed += start.elapsed();
+        nodes += rx.try_iter().last().map_or(0, |info| info.nodes);
+    }
+
+    (nodes, elapsed)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let depth = parse_arg(&args, 0, DEFAULT_DEPTH);
+    let max_threads = parse_arg(
+        &args,
+        1,
+        thread::available_parallelism().map_or(1, |n| n.get()),
+    );
+
+    println!(
+        "{:>8} {:>12} {:>10} {:>10} {:>8}",
+        "threads", "nodes", "time", "nps", "speedup"
+    );
+    let mut base_nps = None;
+    let mut threads = 1;
+    while threads <= max_threads {
+        let (nodes, elapsed) = bench(depth, threads);
+        let nps = nodes as f64 / elapsed.as_secs_f64().max(1e-9);
+        let base_nps = *base_nps.get_or_insert(nps);
+        println!(
+            "{:>8} {:>12} {:>9.2}s {:>10.0} {:>7.2}x",
+            threads,
+            nodes,
+            elapsed.as_secs_f64(),
+            nps,
+            nps / base_nps
+        );
+        threads *= 2;
+    }
+}
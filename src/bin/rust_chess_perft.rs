@@ -0,0 +1,42 @@
+// Counts leaf nodes of the move tree, to compare the move generator against reference numbers.
+// Usage: rust_chess_perft <depth> [fen]
+use rust_chess::fen::START_FEN;
+use rust_chess::perft::divide;
+use rust_chess::state::State;
+use std::env;
+use std::process;
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let depth = match args.first().and_then(|d| d.parse::<u32>().ok()) {
+        Some(depth) => depth,
+        None => {
+            eprintln!("Usage: rust_chess_perft <depth> [fen]");
+            process::exit(1);
+        }
+    };
+    let fen = if args.len() > 1 {
+        args[1..].join(" ")
+    } else {
+        START_FEN.to_string()
+    };
+    let state = State::from_fen(&fen).unwrap_or_else(|e| {
+        eprintln!("Invalid FEN \"{}\": {}", fen, e);
+        process::exit(1);
+    });
+
+    let start = Instant::now();
+    let mut total = 0;
+    for (action, leaves) in divide(&state, depth) {
+        println!("{}: {}", action.to_uci(), leaves);
+        total += leaves;
+    }
+    if depth == 0 {
+        total = 1;
+    }
+
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {:?}", start.elapsed());
+}
@@ -0,0 +1,4 @@
+// Universal Chess Interface front-end, for use with chess GUIs and tournament managers.
+fn main() {
+    rust_chess::uci::uci_loop();
+}
@@ -0,0 +1,4 @@
+// Chess Engine Communication Protocol front-end, for use with xboard, WinBoard and compatible GUIs.
+fn main() {
+    rust_chess::xboard::xboard_loop();
+}
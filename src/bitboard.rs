@@ -0,0 +1,277 @@
+use crate::state::{Piece, PC, PT};
+
+// One bit per square. Bit y * 8 + x corresponds to board[y][x], so the least significant bit is a8
+// and the most significant bit is h1.
+pub type Bitboard = u64;
+
+pub const PAWN: usize = 0;
+pub const KNIGHT: usize = 1;
+pub const BISHOP: usize = 2;
+pub const ROOK: usize = 3;
+pub const QUEEN: usize = 4;
+pub const KING: usize = 5;
+
+pub fn square(y: usize, x: usize) -> usize {
+    y * 8 + x
+}
+
+pub fn coords(square: usize) -> (usize, usize) {
+    (square / 8, square % 8)
+}
+
+pub fn bit(square: usize) -> Bitboard {
+    1 << square
+}
+
+pub fn color_index(c: PC) -> usize {
+    match c {
+        PC::White => 0,
+        PC::Black => 1,
+    }
+}
+
+pub fn piece_index(t: PT) -> usize {
+    match t {
+        PT::Pawn { .. } => PAWN,
+        PT::Knight => KNIGHT,
+        PT::Bishop => BISHOP,
+        PT::Rook { .. } => ROOK,
+        PT::Queen => QUEEN,
+        PT::King { .. } => KING,
+    }
+}
+
+// Iterates over the indices of the set bits, from least to most significant.
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let square = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(square)
+    }
+}
+
+pub fn squares(bitboard: Bitboard) -> Squares {
+    Squares(bitboard)
+}
+
+const KNIGHT_DELTAS: [(isize, isize); 8] = [
+    (-2, -1),
+    (-2, 1),
+    (-1, -2),
+    (-1, 2),
+    (1, -2),
+    (1, 2),
+    (2, -1),
+    (2, 1),
+];
+const KING_DELTAS: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+const WHITE_PAWN_DELTAS: [(isize, isize); 2] = [(-1, -1), (-1, 1)];
+const BLACK_PAWN_DELTAS: [(isize, isize); 2] = [(1, -1), (1, 1)];
+
+// The first four directions are the rook's, the last four the bishop's. A direction is positive
+// when stepping along it increases the square index.
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, -1),
+    (0, 1),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
+
+const fn on_board(y: isize, x: isize) -> bool {
+    y >= 0 && y < 8 && x >= 0 && x < 8
+}
+
+const fn leaper_table(deltas: &[(isize, isize)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (y, x) = ((sq / 8) as isize, (sq % 8) as isize);
+        let mut i = 0;
+        while i < deltas.len() {
+            let (e_y, e_x) = (y + deltas[i].0, x + deltas[i].1);
+            if on_board(e_y, e_x) {
+                table[sq] |= 1 << (e_y * 8 + e_x);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+// Every square reachable from a square in a direction on an empty board.
+const fn ray_table() -> [[Bitboard; 64]; 8] {
+    let mut table = [[0; 64]; 8];
+    let mut dir = 0;
+    while dir < 8 {
+        let mut sq = 0;
+        while sq < 64 {
+            let (mut y, mut x) = ((sq / 8) as isize, (sq % 8) as isize);
+            loop {
+                y += DIRECTIONS[dir].0;
+                x += DIRECTIONS[dir].1;
+                if !on_board(y, x) {
+                    break;
+                }
+                table[dir][sq] |= 1 << (y * 8 + x);
+            }
+            sq += 1;
+        }
+        dir += 1;
+    }
+    table
+}
+
+const KNIGHT_ATTACKS: [Bitboard; 64] = leaper_table(&KNIGHT_DELTAS);
+const KING_ATTACKS: [Bitboard; 64] = leaper_table(&KING_DELTAS);
+const PAWN_ATTACKS: [[Bitboard; 64]; 2] = [
+    leaper_table(&WHITE_PAWN_DELTAS),
+    leaper_table(&BLACK_PAWN_DELTAS),
+];
+const RAYS: [[Bitboard; 64]; 8] = ray_table();
+
+pub fn knight_attacks(square: usize) -> Bitboard {
+    KNIGHT_ATTACKS[square]
+}
+
+pub fn king_attacks(square: usize) -> Bitboard {
+    KING_ATTACKS[square]
+}
+
+// Squares a pawn of color c standing on square attacks.
+pub fn pawn_attacks(c: PC, square: usize) -> Bitboard {
+    PAWN_ATTACKS[color_index(c)][square]
+}
+
+// The ray stops at the first occupied square, which is included so that captures are generated.
+fn ray_attacks(square: usize, dir: usize, occupied: Bitboard) -> Bitboard {
+    let ray = RAYS[dir][square];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+
+    let (d_y, d_x) = DIRECTIONS[dir];
+    let first_blocker = if d_y > 0 || (d_y == 0 && d_x > 0) {
+        blockers.trailing_zeros() as usize
+    } else {
+        63 - blockers.leading_zeros() as usize
+    };
+
+    ray ^ RAYS[dir][first_blocker]
+}
+
+pub fn rook_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    (0..4).fold(0, |acc, dir| acc | ray_attacks(square, dir, occupied))
+}
+
+pub fn bishop_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    (4..8).fold(0, |acc, dir| acc | ray_attacks(square, dir, occupied))
+}
+
+pub fn queen_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+// Piece placement by color and type, kept in sync with State::board.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bitboards {
+    pub pieces: [[Bitboard; 6]; 2],
+    pub colors: [Bitboard; 2],
+}
+
+impl Bitboards {
+    pub fn from_board(board: &[[Option<Piece>; 8]; 8]) -> Self {
+        let mut bitboards = Bitboards::default();
+        for (y, row) in board.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                if let Some(piece) = piece {
+                    bitboards.set(square(y, x), piece);
+                }
+            }
+        }
+        bitboards
+    }
+
+    pub fn set(&mut self, square: usize, piece: &Piece) {
+        let c = color_index(piece.c);
+        self.pieces[c][piece_index(piece.t)] |= bit(square);
+        self.colors[c] |= bit(square);
+    }
+
+    pub fn clear(&mut self, square: usize, piece: &Piece) {
+        let c = color_index(piece.c);
+        self.pieces[c][piece_index(piece.t)] &= !bit(square);
+        self.colors[c] &= !bit(square);
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn of(&self, c: PC, piece: usize) -> Bitboard {
+        self.pieces[color_index(c)][piece]
+    }
+
+    pub fn king_square(&self, c: PC) -> Option<usize> {
+        squares(self.of(c, KING)).next()
+    }
+
+    // Pieces of either color attacking square, as if only the squares in occupied were occupied.
+    pub fn attackers_to(&self, square: usize, occupied: Bitboard) -> Bitboard {
+        let pawns = (pawn_attacks(PC::Black, square) & self.of(PC::White, PAWN))
+            | (pawn_attacks(PC::White, square) & self.of(PC::Black, PAWN));
+        let knights = self.of(PC::White, KNIGHT) | self.of(PC::Black, KNIGHT);
+        let kings = self.of(PC::White, KING) | self.of(PC::Black, KING);
+        let diagonal = self.of(PC::White, BISHOP)
+            | self.of(PC::Black, BISHOP)
+            | self.of(PC::White, QUEEN)
+            | self.of(PC::Black, QUEEN);
+        let straight = self.of(PC::White, ROOK)
+            | self.of(PC::Black, ROOK)
+            | self.of(PC::White, QUEEN)
+            | self.of(PC::Black, QUEEN);
+
+        (pawns
+            | (knight_attacks(square) & knights)
+            | (king_attacks(square) & kings)
+            | (bishop_attacks(square, occupied) & diagonal)
+            | (rook_attacks(square, occupied) & straight))
+            & occupied
+    }
+
+    // Whether any piece of color by attacks square.
+    pub fn is_attacked(&self, square: usize, by: PC) -> bool {
+        let occupied = self.occupied();
+        let diagonal = self.of(by, BISHOP) | self.of(by, QUEEN);
+        let straight = self.of(by, ROOK) | self.of(by, QUEEN);
+
+        // A pawn of the other color on square would attack exactly the squares from which pawns
+        // of color by attack square.
+        pawn_attacks(by.opponent(), square) & self.of(by, PAWN) != 0
+            || knight_attacks(square) & self.of(by, KNIGHT) != 0
+            || king_attacks(square) & self.of(by, KING) != 0
+            || bishop_attacks(square, occupied) & diagonal != 0
+            || rook_attacks(square, occupied) & straight != 0
+    }
+}
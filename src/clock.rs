@@ -0,0 +1,461 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::bitboard::color_index;
+use crate::state::PC;
+use crate::time_manager::TimeBudget;
+
+// One period of a classical time control: the time for the next moves moves, or for the rest of
+// the game when moves is None. A last period with a number of moves is repeated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Period {
+    pub moves: Option<u32>,
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimeControl {
+    // The increment is added to the clock after every move.
+    Fischer { base: Duration, increment: Duration },
+    // The time spent on a move is given back after it, up to the delay.
+    Bronstein { base: Duration, delay: Duration },
+    Classical(Vec<Period>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TimeControlError(String);
+
+impl fmt::Display for TimeControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid time control '{}', expected e.g. 300+5, 300d5 or 40/7200:3600",
+            self.0
+        )
+    }
+}
+
+impl Error for TimeControlError {}
+
+fn parse_seconds(s: &str) -> Option<Duration> {
+    // Negative, infinite and too large times are rejected here.
+    Duration::try_from_secs_f64(s.parse::<f64>().ok()?).ok()
+}
+
+fn format_seconds(d: Duration) -> String {
+    if d.subsec_millis() == 0 {
+        d.as_secs().to_string()
+    } else {
+        d.as_secs_f64().to_string()
+    }
+}
+
+// Times are in seconds, as in the TimeControl tag of PGN: "300+5" is five minutes with a five
+// second increment, "300d5" the same with a five second delay, and "40/7200:3600" two hours for
+// forty moves followed by an hour for the rest of the game.
+impl FromStr for TimeControl {
+    type Err = TimeControlError;
+
+    fn from_str(s: &str) -> Result<TimeControl, TimeControlError> {
+        let invalid = || TimeControlError(s.to_string());
+
+        if let Some((base, increment)) = s.split_once('+') {
+            return Ok(TimeControl::Fischer {
+                base: parse_seconds(base).ok_or_else(invalid)?,
+                increment: parse_seconds(increment).ok_or_else(invalid)?,
+            });
+        }
+        if let Some((base, delay)) = s.split_once('d') {
+            return Ok(TimeControl::Bronstein {
+                base: parse_seconds(base).ok_or_else(invalid)?,
+                delay: parse_seconds(delay).ok_or_else(invalid)?,
+            });
+        }
+        if !s.contains('/') {
+            return Ok(TimeControl::Fischer {
+                base: parse_seconds(s).ok_or_else(invalid)?,
+                increment: Duration::ZERO,
+            });
+        }
+
+        let periods = s
+            .split(':')
+            .map(|period| match period.split_once('/') {
+                Some((moves, time)) => Some(Period {
+                    moves: Some(moves.parse::<u32>().ok().filter(|m| *m > 0)?),
+                    time: parse_seconds(time)?,
+                }),
+                None => Some(Period {
+                    moves: None,
+                    time: parse_seconds(period)?,
+                }),
+            })
+            .collect::<Option<Vec<Period>>>()
+            .ok_or_else(invalid)?;
+
+        // Only the last period may cover the rest of the game.
+        if periods[..periods.len() - 1]
+            .iter()
+            .any(|p| p.moves.is_none())
+        {
+            return Err(invalid());
+        }
+        Ok(TimeControl::Classical(periods))
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeControl::Fischer { base, increment } if increment.is_zero() => {
+                write!(f, "{}", format_seconds(*base))
+            }
+            TimeControl::Fischer { base, increment } => {
+                write!(
+                    f,
+                    "{}+{}",
+                    format_seconds(*base),
+                    format_seconds(*increment)
+                )
+            }
+            TimeControl::Bronstein { base, delay } => {
+                write!(f, "{}d{}", format_seconds(*base), format_seconds(*delay))
+            }
+            TimeControl::Classical(periods) => {
+                let periods: Vec<String> = periods
+                    .iter()
+                    .map(|period| match period.moves {
+                        Some(moves) => format!("{}/{}", moves, format_seconds(period.time)),
+                        None => format_seconds(period.time),
+                    })
+                    .collect();
+                write!(f, "{}", periods.join(":"))
+            }
+        }
+    }
+}
+
+impl TimeControl {
+    // The value of the PGN TimeControl tag, which has no notation for a delay.
+    pub fn pgn_tag(&self) -> Option<String> {
+        match self {
+            TimeControl::Bronstein { .. } => None,
+            _ => Some(self.to_string()),
+        }
+    }
+
+    fn initial_time(&self) -> Duration {
+        match self {
+            TimeControl::Fischer { base, .. } | TimeControl::Bronstein { base, .. } => *base,
+            TimeControl::Classical(periods) => periods[0].time,
+        }
+    }
+}
+
+// Formats a clock reading as h:mm:ss, or m:ss, with tenths of a second below ten seconds.
+pub fn format_clock(d: Duration) -> String {
+    let tenths = d.as_millis() / 100;
+    let seconds = tenths / 10;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 10 {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("0:{:02}.{}", seconds, tenths % 10)
+    }
+}
+
+// A chess clock for both players. Only the clock of the side to move runs.
+pub struct ChessClock {
+    time_control: TimeControl,
+    remaining: [Duration; 2],
+    // Moves made by each side in its current period of a classical time control.
+    moves_in_period: [u32; 2],
+    period_index: [usize; 2],
+    running: Option<(PC, Instant)>,
+}
+
+impl ChessClock {
+    pub fn new(time_control: TimeControl) -> Self {
+        let initial_time = time_control.initial_time();
+        ChessClock {
+            time_control,
+            remaining: [initial_time; 2],
+            moves_in_period: [0; 2],
+            period_index: [0; 2],
+            running: None,
+        }
+    }
+
+    pub fn time_control(&self) -> &TimeControl {
+        &self.time_control
+    }
+
+    pub fn running_side(&self) -> Option<PC> {
+        self.running.map(|(c, _)| c)
+    }
+
+    pub fn remaining(&self, c: PC) -> Duration {
+        let remaining = self.remaining[color_index(c)];
+        match self.running {
+            Some((running, since)) if running == c => remaining.saturating_sub(since.elapsed()),
+            _ => remaining,
+        }
+    }
+
+    // Stops the clock that is running, without counting a move, and starts the one of c.
+    pub fn start(&mut self, c: PC) {
+        self.stop();
+        self.running = Some((c, Instant::now()));
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((c, since)) = self.running.take() {
+            let i = color_index(c);
+            self.remaining[i] = self.remaining[i].saturating_sub(since.elapsed());
+        }
+    }
+
+    // Ends the move of the side whose clock runs, adding whatever the time control gives for it,
+    // and starts the clock of the opponent. Nothing is added once the flag has fallen.
+    pub fn press(&mut self) {
+        let (c, since) = match self.running {
+            Some(running) => running,
+            None => return,
+        };
+        let i = color_index(c);
+        let spent = since.elapsed();
+        let flag_fell = spent >= self.remaining[i];
+        self.running = None;
+        self.remaining[i] = self.remaining[i].saturating_sub(spent);
+
+        if !flag_fell {
+            match &self.time_control {
+                TimeControl::Fischer { increment, .. } => self.remaining[i] += *increment,
+                TimeControl::Bronstein { delay, .. } => self.remaining[i] += spent.min(*delay),
+                TimeControl::Classical(periods) => {
+                    self.moves_in_period[i] += 1;
+                    if periods[self.period_index[i]].moves == Some(self.moves_in_period[i]) {
+                        self.period_index[i] = (self.period_index[i] + 1).min(periods.len() - 1);
+                        self.moves_in_period[i] = 0;
+                        self.remaining[i] += periods[self.period_index[i]].time;
+                    }
+                }
+            }
+        }
+
+        self.running = Some((c.opponent(), Instant::now()));
+    }
+
+    // The side whose time has run out, if any.
+    pub fn flagged(&self) -> Option<PC> {
+        [PC::White, PC::Black]
+            .iter()
+            .find(|c| self.remaining(**c).is_zero())
+            .copied()
+    }
+
+    // How long c should think about its next move.
+    pub fn time_budget(&self, c: PC) -> TimeBudget {
+        let remaining = self.remaining(c);
+        match &self.time_control {
+            TimeControl::Fischer { increment, .. } => {
+                TimeBudget::from_clock(remaining, *increment, None)
+            }
+            TimeControl::Bronstein { delay, .. } => TimeBudget::from_clock(remaining, *delay, None),
+            TimeControl::Classical(periods) => {
+                let i = color_index(c);
+                let moves_to_go = periods[self.period_index[i]]
+                    .moves
+                    .map(|moves| moves - self.moves_in_period[i]);
+                TimeBudget::from_clock(remaining, Duration::ZERO, moves_to_go)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use crate::value::{timeout_result, GameResult, Termination};
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let cases = [
+            (
+                "300+5",
+                TimeControl::Fischer {
+                    base: secs(300),
+                    increment: secs(5),
+                },
+            ),
+            (
+                "600",
+                TimeControl::Fischer {
+                    base: secs(600),
+                    increment: Duration::ZERO,
+                },
+            ),
+            (
+                "0.5+0.1",
+                TimeControl::Fischer {
+                    base: Duration::from_millis(500),
+                    increment: Duration::from_millis(100),
+                },
+            ),
+            (
+                "300d5",
+                TimeControl::Bronstein {
+                    base: secs(300),
+                    delay: secs(5),
+                },
+            ),
+            (
+                "40/7200:3600",
+                TimeControl::Classical(vec![
+                    Period {
+                        moves: Some(40),
+                        time: secs(7200),
+                    },
+                    Period {
+                        moves: None,
+                        time: secs(3600),
+                    },
+                ]),
+            ),
+            (
+                "40/7200:20/3600",
+                TimeControl::Classical(vec![
+                    Period {
+                        moves: Some(40),
+                        time: secs(7200),
+                    },
+                    Period {
+                        moves: Some(20),
+                        time: secs(3600),
+                    },
+                ]),
+            ),
+        ];
+        for (s, time_control) in cases.iter() {
+            assert_eq!(s.parse::<TimeControl>().as_ref(), Ok(time_control), "{}", s);
+            assert_eq!(time_control.to_string(), *s);
+        }
+        assert_eq!("300d5".parse::<TimeControl>().unwrap().pgn_tag(), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            "",
+            "abc",
+            "-5",
+            "300+",
+            "+5",
+            "300+-5",
+            "300dx",
+            "inf",
+            "NaN+5",
+            "1e20",
+            "1e20+5",
+            "300d1e30",
+            "0/60",
+            "40/1e20:60",
+            "7200:40/3600",
+            "40/",
+        ];
+        for s in cases.iter() {
+            assert!(s.parse::<TimeControl>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn increments_and_periods() {
+        let mut clock = ChessClock::new("60+5".parse().unwrap());
+        clock.start(PC::White);
+        clock.press();
+        assert_eq!(clock.running_side(), Some(PC::Black));
+        assert!(clock.remaining(PC::White) > secs(64));
+        assert!(clock.remaining(PC::Black) <= secs(60));
+        clock.stop();
+        assert!(clock.remaining(PC::Black) > secs(59));
+
+        // Within a second the Bronstein delay gives back all the time spent.
+        let mut clock = ChessClock::new("60d5".parse().unwrap());
+        clock.start(PC::White);
+        clock.press();
+        assert_eq!(clock.remaining(PC::White), secs(60));
+
+        let mut clock = ChessClock::new("2/60:30".parse().unwrap());
+        for _ in 0..2 {
+            clock.start(PC::White);
+            clock.press();
+        }
+        clock.stop();
+        assert!(clock.remaining(PC::White) > secs(89));
+        assert!(clock.remaining(PC::White) <= secs(90));
+    }
+
+    #[test]
+    fn flag_fall() {
+        let mut clock = ChessClock::new("0+5".parse().unwrap());
+        assert_eq!(clock.flagged(), Some(PC::White));
+        clock.start(PC::White);
+        clock.press();
+        // No increment once the flag has fallen.
+        assert_eq!(clock.remaining(PC::White), Duration::ZERO);
+
+        let mut clock = ChessClock::new("60".parse().unwrap());
+        assert_eq!(clock.flagged(), None);
+        clock.start(PC::Black);
+        assert_eq!(clock.flagged(), None);
+        assert_eq!(clock.running_side(), Some(PC::Black));
+    }
+
+    #[test]
+    fn flag_fall_results() {
+        let cases = [
+            (
+                "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+                PC::White,
+                GameResult::Tie(Termination::Timeout),
+            ),
+            (
+                "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+                PC::Black,
+                GameResult::WhiteWin(Termination::Timeout),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+                PC::Black,
+                GameResult::Tie(Termination::Timeout),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1",
+                PC::Black,
+                GameResult::WhiteWin(Termination::Timeout),
+            ),
+            (
+                "4k3/4p3/8/8/8/8/8/2B1K3 w - - 0 1",
+                PC::Black,
+                GameResult::WhiteWin(Termination::Timeout),
+            ),
+        ];
+        for (fen, flagged, result) in cases.iter() {
+            let state = State::from_fen(fen).unwrap();
+            assert_eq!(timeout_result(&state, *flagged), *result, "{}", fen);
+        }
+    }
+}
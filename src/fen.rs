@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::actions::is_king_attacked;
+use crate::bitboard::Bitboards;
+use crate::state::{Piece, State, PC, PT};
+use crate::zobrist::full_key;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FenError {
+    WrongFieldCount(usize),
+    WrongRankCount(usize),
+    WrongRankLength { rank: usize, length: usize },
+    UnknownPiece(char),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    InvalidEnPassant(String),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    WrongKingCount { color: PC, count: usize },
+    PawnOnBackRank { rank: usize, file: char },
+    OpponentInCheck,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::WrongFieldCount(n) => {
+                write!(f, "expected 4 or 6 space-separated fields, found {}", n)
+            }
+            FenError::WrongRankCount(n) => write!(f, "expected 8 ranks, found {}", n),
+            FenError::WrongRankLength { rank, length } => {
+                write!(f, "rank {} describes {} squares instead of 8", rank, length)
+            }
+            FenError::UnknownPiece(c) => write!(f, "unknown piece character '{}'", c),
+            FenError::InvalidSideToMove(s) => {
+                write!(f, "side to move must be 'w' or 'b', found '{}'", s)
+            }
+            FenError::InvalidCastling(s) => write!(f, "invalid castling rights '{}'", s),
+            FenError::InvalidEnPassant(s) => write!(f, "invalid en-passant square '{}'", s),
+            FenError::InvalidHalfmoveClock(s) => write!(f, "invalid halfmove clock '{}'", s),
+            FenError::InvalidFullmoveNumber(s) => write!(f, "invalid fullmove number '{}'", s),
+            FenError::WrongKingCount { color, count } => {
+                write!(f, "{:?} must have exactly one king, found {}", color, count)
+            }
+            FenError::PawnOnBackRank { rank, file } => {
+                write!(f, "pawn found on back rank at {}{}", file, rank)
+            }
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl Error for FenError {}
+
+fn fen_char_to_piece(c: char) -> Option<Piece> {
+    let color = if c.is_ascii_uppercase() {
+        PC::White
+    } else {
+        PC::Black
+    };
+    let t = match c.to_ascii_lowercase() {
+        'p' => PT::Pawn { last_jump: None },
+        'n' => PT::Knight,
+        'b' => PT::Bishop,
+        'r' => PT::Rook { has_moved: true },
+        'q' => PT::Queen,
+        'k' => PT::King { has_moved: true },
+        _ => return None,
+    };
+
+    Some(Piece { c: color, t })
+}
+
+fn piece_to_fen_char(p: &Piece) -> char {
+    let c = match p.t {
+        PT::Pawn { .. } => 'p',
+        PT::Knight => 'n',
+        PT::Bishop => 'b',
+        PT::Rook { .. } => 'r',
+        PT::Queen => 'q',
+        PT::King { .. } => 'k',
+    };
+
+    match p.c {
+        PC::Black => c,
+        PC::White => c.to_ascii_uppercase(),
+    }
+}
+
+// Parses a square such as "e3" into board coordinates.
+pub fn parse_square(s: &str) -> Option<(usize, usize)> {
+    let mut chars = s.chars();
+    let (file, rank) = (chars.next()?, chars.next()?);
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+
+    Some((
+        8 - rank.to_digit(10)? as usize,
+        (file as u8 - b'a') as usize,
+    ))
+}
+
+pub fn square_to_string(y: usize, x: usize) -> String {
+    format!("{}{}", (b'a' + x as u8) as char, 8 - y)
+}
+
+fn parse_board(placement: &str) -> Result<[[Option<Piece>; 8]; 8], FenError> {
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::WrongRankCount(ranks.len()));
+    }
+
+    let mut board = [[None; 8]; 8];
+    for (y, rank) in ranks.iter().enumerate() {
+        let mut x = 0;
+        for c in rank.chars() {
+            if let Some(empty) = c.to_digit(10) {
+                if !(1..=8).contains(&empty) {
+                    return Err(FenError::UnknownPiece(c));
+                }
+                x += empty as usize;
+            } else {
+                let piece = fen_char_to_piece(c).ok_or(FenError::UnknownPiece(c))?;
+                if x < 8 {
+                    board[y][x] = Some(piece);
+                }
+                x += 1;
+            }
+        }
+        if x != 8 {
+            return Err(FenError::WrongRankLength {
+                rank: 8 - y,
+                length: x,
+            });
+        }
+    }
+
+    for color in [PC::White, PC::Black].iter() {
+        let count = board
+            .iter()
+            .flatten()
+            .filter(|p| {
+                matches!(p, Some(Piece {
+                    c,
+                    t: PT::King { .. },
+                }) if c == color)
+            })
+            .count();
+        if count != 1 {
+            return Err(FenError::WrongKingCount {
+                color: *color,
+                count,
+            });
+        }
+    }
+
+    for y in [0, 7].iter() {
+        for (x, square) in board[*y].iter().enumerate() {
+            if let Some(Piece {
+                t: PT::Pawn { .. }, ..
+            }) = square
+            {
+                return Err(FenError::PawnOnBackRank {
+                    rank: 8 - y,
+                    file: (b'a' + x as u8) as char,
+                });
+            }
+        }
+    }
+
+    Ok(board)
+}
+
+// Castling rights are stored on the pieces themselves: a right exists when both the king and the
+// corresponding rook are on their initial squares and have not moved.
+fn apply_castling(board: &mut [[Option<Piece>; 8]; 8], castling: &str) -> Result<(), FenError> {
+    if castling == "-" {
+        return Ok(());
+    }
+
+    // The rights are listed at most once each, in the order KQkq.
+    let mut previous = None;
+    for c in castling.chars() {
+        let index = "KQkq".find(c);
+        if index.is_none() || index <= previous {
+            return Err(FenError::InvalidCastling(castling.to_string()));
+        }
+        previous = index;
+
+        let (color, y, rook_x) = match c {
+            'K' => (PC::White, 7, 7),
+            'Q' => (PC::White, 7, 0),
+            'k' => (PC::Black, 0, 7),
+            _ => (PC::Black, 0, 0),
+        };
+
+        match (board[y][4], board[y][rook_x]) {
+            (
+                Some(Piece {
+                    c: king_color,
+                    t: PT::King { .. },
+                }),
+                Some(Piece {
+                    c: rook_color,
+                    t: PT::Rook { .. },
+                }),
+            ) if king_color == color && rook_color == color => {
+                board[y][4] = Some(Piece {
+                    c: color,
+                    t: PT::King { has_moved: false },
+                });
+                board[y][rook_x] = Some(Piece {
+                    c: color,
+                    t: PT::Rook { has_moved: false },
+                });
+            }
+            _ => return Err(FenError::InvalidCastling(castling.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+impl State {
+    pub fn from_fen(fen: &str) -> Result<State, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() != 4 && fields.len() != 6 {
+            return Err(FenError::WrongFieldCount(fields.len()));
+        }
+
+        let mut board = parse_board(fields[0])?;
+
+        let turn = match fields[1] {
+            "w" => PC::White,
+            "b" => PC::Black,
+            s => return Err(FenError::InvalidSideToMove(s.to_string())),
+        };
+
+        apply_castling(&mut board, fields[2])?;
+
+        let (halfmove_clock, fullmove_number) = if fields.len() == 6 {
+            let halfmove_clock = fields[4]
+                .parse::<usize>()
+                .map_err(|_| FenError::InvalidHalfmoveClock(fields[4].to_string()))?;
+            // Moves are numbered from 1.
+            let fullmove_number = fields[5]
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n >= 1)
+                .ok_or_else(|| FenError::InvalidFullmoveNumber(fields[5].to_string()))?;
+            (halfmove_clock, fullmove_number)
+        } else {
+            (0, 1)
+        };
+
+        let moves = 2 * (fullmove_number - 1)
+            + match turn {
+                PC::White => 0,
+                PC::Black => 1,
+            };
+
+        // The en-passant square is represented by the pawn that jumped over it on the previous
+        // move.
+        if fields[3] != "-" {
+            let invalid = || FenError::InvalidEnPassant(fields[3].to_string());
+            let (ep_y, ep_x) = parse_square(fields[3]).ok_or_else(invalid)?;
+            let (expected_y, pawn_y) = match turn {
+                PC::White => (2, 3),
+                PC::Black => (5, 4),
+            };
+            if ep_y != expected_y || moves == 0 || board[ep_y][ep_x].is_some() {
+                return Err(invalid());
+            }
+            match board[pawn_y][ep_x].as_mut() {
+                Some(Piece {
+                    c,
+                    t: PT::Pawn { last_jump },
+                }) if *c == turn.opponent() => {
+                    *last_jump = Some(moves - 1);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        let mut state = State {
+            board,
+            bitboards: Bitboards::from_board(&board),
+            hash_key: 0,
+            hash_to_occurences: HashMap::new(),
+            turn,
+            moves,
+            halfmove_clock,
+            drawn: None,
+        };
+        state.hash_key = full_key(&state);
+
+        if is_king_attacked(&state, turn.opponent()) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        *state.hash_to_occurences.entry(state.hash()).or_insert(0) += 1;
+
+        Ok(state)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let placement = self
+            .board
+            .iter()
+            .map(|row| {
+                let mut rank = String::new();
+                let mut empty = 0;
+                for square in row {
+                    match square {
+                        None => empty += 1,
+                        Some(p) => {
+                            if empty > 0 {
+                                rank.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+                            rank.push(piece_to_fen_char(p));
+                        }
+                    }
+                }
+                if empty > 0 {
+                    rank.push_str(&empty.to_string());
+                }
+                rank
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let turn = match self.turn {
+            PC::White => "w",
+            PC::Black => "b",
+        };
+
+        let castling: String = ['K', 'Q', 'k', 'q']
+            .iter()
+            .zip(self.castling_rights().iter())
+            .filter(|(_, right)| **right)
+            .map(|(c, _)| c)
+            .collect();
+        let castling = if castling.is_empty() {
+            "-".to_string()
+        } else {
+            castling
+        };
+
+        let en_passant = match self.en_passant_file() {
+            Some(x) => match self.turn {
+                PC::White => square_to_string(2, x),
+                PC::Black => square_to_string(5, x),
+            },
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            turn,
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.moves / 2 + 1
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [&str; 8] = [
+        START_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3",
+    ];
+
+    #[test]
+    fn round_trip() {
+        for fen in POSITIONS.iter() {
+            assert_eq!(State::from_fen(fen).unwrap().to_fen(), *fen);
+        }
+        // The move counters may be left out.
+        assert_eq!(
+            State::from_fen("4k3/8/8/8/8/8/8/4K2R w K -")
+                .unwrap()
+                .to_fen(),
+            "4k3/8/8/8/8/8/8/4K2R w K - 0 1"
+        );
+    }
+
+    #[test]
+    fn invalid_castling() {
+        let board = "r3k2r/8/8/8/8/8/8/R3K2R w";
+        for castling in ["KKqq", "qK", "QK", "kK", "KQkqK", "KQx", "--"].iter() {
+            assert_eq!(
+                State::from_fen(&format!("{} {} - 0 1", board, castling)),
+                Err(FenError::InvalidCastling(castling.to_string()))
+            );
+        }
+        for castling in ["K", "Qk", "KQkq", "-"].iter() {
+            assert!(State::from_fen(&format!("{} {} - 0 1", board, castling)).is_ok());
+        }
+        // Without the rook on its square.
+        assert_eq!(
+            State::from_fen("r3k2r/8/8/8/8/8/8/R3K1R1 w K - 0 1"),
+            Err(FenError::InvalidCastling("K".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_move_numbers() {
+        assert_eq!(
+            State::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 0"),
+            Err(FenError::InvalidFullmoveNumber("0".to_string()))
+        );
+        assert_eq!(
+            State::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 x"),
+            Err(FenError::InvalidFullmoveNumber("x".to_string()))
+        );
+        assert_eq!(
+            State::from_fen("4k3/8/8/8/8/8/8/4K3 w - - -1 1"),
+            Err(FenError::InvalidHalfmoveClock("-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_en_passant() {
+        let cases = [
+            // Not on the third or sixth rank, or on the wrong one for the side to move.
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d5 0 2",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d3 0 2",
+            // No pawn that could have jumped, or an occupied square.
+            "4k3/8/8/4P3/8/8/8/4K3 w - d6 0 2",
+            "4k3/8/3n4/3pP3/8/8/8/4K3 w - d6 0 2",
+            // Before any move was made.
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - z6 0 2",
+        ];
+        for fen in cases.iter() {
+            let en_passant = fen.split(' ').nth(3).unwrap();
+            assert_eq!(
+                State::from_fen(fen),
+                Err(FenError::InvalidEnPassant(en_passant.to_string())),
+                "{}",
+                fen
+            );
+        }
+        assert!(State::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").is_ok());
+    }
+}
@@ -0,0 +1,26 @@
+#[cfg(feature = "gui")]
+extern crate sfml;
+
+pub mod action;
+pub mod actions;
+pub mod ai;
+pub mod bitboard;
+pub mod book;
+pub mod clock;
+pub mod fen;
+pub mod ordering;
+pub mod perft;
+pub mod pgn;
+pub mod result;
+pub mod san;
+pub mod see;
+pub mod state;
+pub mod syzygy;
+pub mod time_manager;
+pub mod tt;
+pub mod uci;
+#[cfg(feature = "gui")]
+pub mod ui;
+pub mod value;
+pub mod xboard;
+pub mod zobrist;
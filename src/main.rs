@@ -1,17 +1,14 @@
-extern crate sfml;
-
-pub mod action;
-pub mod actions;
-pub mod ai;
-pub mod result;
-pub mod state;
-pub mod ui;
-pub mod value;
-
-use state::PC;
+use rust_chess::ai::SearchOptions;
+use rust_chess::book::OpeningBook;
+use rust_chess::clock::TimeControl;
+use rust_chess::pgn::parse_pgn;
+use rust_chess::state::{State, PC};
+use rust_chess::syzygy::Tablebase;
+use rust_chess::ui::{ui_routine, Thinker};
 use std::collections::HashMap;
+use std::env;
+use std::fs;
 use std::process;
-use ui::{ui_routine, Thinker};
 
 fn main() {
     fern::Dispatch::new()
@@ -42,5 +39,116 @@ fn main() {
     color_assignments.insert(PC::White, Thinker::Player);
     color_assignments.insert(PC::Black, Thinker::AI);
 
-    ui_routine(color_assignments);
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // "--time <control>" plays with clocks, see TimeControl for the notation.
+    let time_control = take_option(&mut args, "--time", "a time control, e.g. 300+5").map(|tc| {
+        tc.parse::<TimeControl>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+
+    // "--book <file>" lets the AI play its first moves from a Polyglot opening book, for at most
+    // "--book-depth <plies>".
+    let book_depth = take_option(&mut args, "--book-depth", "a number of plies").map(|depth| {
+        depth.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Invalid book depth \"{}\"", depth);
+            process::exit(1);
+        })
+    });
+    let book = take_option(&mut args, "--book", "a Polyglot book file").map(|path| {
+        let mut book = OpeningBook::open(&path).unwrap_or_else(|e| {
+            eprintln!("Could not read book {}: {}", path, e);
+            process::exit(1);
+        });
+        if let Some(depth) = book_depth {
+            book.depth = depth;
+        }
+        book
+    });
+
+    // "--syzygy <dirs>" lets the AI play endgames from Syzygy tablebases.
+    let tablebase = take_option(&mut args, "--syzygy", "a tablebase directory").map(|path| {
+        Tablebase::open(&path).unwrap_or_else(|e| {
+            eprintln!("Could not read tablebases {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+
+    // "--threads <n>" lets the AI search with n threads.
+    let threads = take_option(&mut args, "--threads", "a number of threads").map(|threads| {
+        threads.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Invalid number of threads \"{}\"", threads);
+            process::exit(1);
+        })
+    });
+    // "--contempt <centipawns>" sets how much the AI dislikes draws.
+    let contempt = take_option(&mut args, "--contempt", "a number of centipawns").map(|contempt| {
+        contempt.parse::<i32>().unwrap_or_else(|_| {
+            eprintln!("Invalid contempt \"{}\"", contempt);
+            process::exit(1);
+        })
+    });
+    let mut options = SearchOptions::default();
+    if let Some(threads) = threads {
+        options.threads = threads.max(1);
+    }
+    if let Some(contempt) = contempt {
+        options.contempt = contempt;
+    }
+
+    // An optional argument sets up the starting position, either as a FEN string or as a PGN
+    // file whose first game is replayed.
+    let (history, played_actions) = match args.into_iter().next() {
+        Some(path) if path.ends_with(".pgn") => {
+            let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+                eprintln!("Could not read {}: {}", path, e);
+                process::exit(1);
+            });
+            let game = match parse_pgn(&text) {
+                Ok(mut games) if !games.is_empty() => games.swap_remove(0),
+                Ok(_) => {
+                    eprintln!("No games found in {}", path);
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Invalid PGN in {}: {}", path, e);
+                    process::exit(1);
+                }
+            };
+            let played_actions = game.moves.iter().map(|m| m.action).collect();
+            (game.states(), played_actions)
+        }
+        Some(fen) => {
+            let state = State::from_fen(&fen).unwrap_or_else(|e| {
+                eprintln!("Invalid FEN \"{}\": {}", fen, e);
+                process::exit(1);
+            });
+            (vec![state], vec![])
+        }
+        None => (vec![State::new()], vec![]),
+    };
+
+    ui_routine(
+        color_assignments,
+        history,
+        played_actions,
+        time_control,
+        book,
+        tablebase,
+        options,
+    );
+}
+
+// Removes "<name> <value>" from args and returns the value, exiting when the value is missing.
+fn take_option(args: &mut Vec<String>, name: &str, expected: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    if i + 1 >= args.len() {
+        eprintln!("{} needs {}", name, expected);
+        process::exit(1);
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Some(value)
 }
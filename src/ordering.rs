@@ -0,0 +1,222 @@
+use crate::action::Action;
+use crate::bitboard::{color_index, piece_index, square};
+use crate::see::see;
+use crate::state::State;
+use crate::value::heuristic_action;
+
+// Piece values for ordering captures, indexed like the bitboards.
+const ORDER_VALUES: [i32; 6] = [1, 3, 3, 5, 9, 20];
+// History scores stay between -HISTORY_MAX and HISTORY_MAX.
+const HISTORY_MAX: i32 = 16384;
+const MAX_HISTORY_BONUS: i32 = 400;
+
+// Groups of actions in the order they are searched. Within a group, actions are sorted by their
+// score, then by heuristic_action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    LosingCapture,
+    Quiet,
+    Countermove,
+    Killer,
+    Capture,
+    CachedAction,
+}
+
+// Moves a piece without capturing or promoting. These are the actions that killers, countermoves
+// and the history are kept for, since captures are ordered well enough by what they take.
+pub fn is_quiet(action: &Action) -> bool {
+    matches!(action, Action::Jump { .. } | Action::Castling { .. })
+}
+
+fn from_to(action: &Action) -> usize {
+    let (s_y, s_x) = action.get_main_coords();
+    let (e_y, e_x) = action.get_end_coords();
+    square(s_y, s_x) * 64 + square(e_y, e_x)
+}
+
+// Most valuable victim, then least valuable attacker.
+fn mvv_lva(state: &State, action: &Action) -> i32 {
+    let attacker = match action.get_main_piece(state) {
+        Some(piece) => ORDER_VALUES[piece_index(piece.t)],
+        None => return 0,
+    };
+    let victim = match *action {
+        Action::Capture { e_y, e_x, .. } | Action::Promotion { e_y, e_x, .. } => {
+            state.board[e_y][e_x].map_or(0, |piece| ORDER_VALUES[piece_index(piece.t)])
+        }
+        Action::Enpassant { .. } => ORDER_VALUES[0],
+        _ => 0,
+    };
+    let promotion = match *action {
+        Action::Promotion { to_piece, .. } => ORDER_VALUES[piece_index(to_piece)],
+        _ => 0,
+    };
+
+    100 * (victim + promotion) - attacker
+}
+
+// What earlier parts of a search learned about which quiet actions cause cutoffs: two killers per
+// ply, the reply that refuted each previous action, and a history score for every side, start
+// square and end square.
+pub struct MoveOrdering {
+    killers: Vec<[Option<Action>; 2]>,
+    countermoves: Vec<Option<Action>>,
+    history: Vec<i32>,
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        MoveOrdering::new()
+    }
+}
+
+impl MoveOrdering {
+    pub fn new() -> Self {
+        MoveOrdering {
+            killers: Vec::new(),
+            countermoves: vec![None; 64 * 64],
+            history: vec![0; 2 * 64 * 64],
+        }
+    }
+
+    fn history_index(state: &State, action: &Action) -> usize {
+        color_index(state.turn) * 64 * 64 + from_to(action)
+    }
+
+    fn countermove(&self, previous_action: Option<Action>) -> Option<Action> {
+        previous_action
+            .filter(|previous| *previous != Action::Tie)
+            .and_then(|previous| self.countermoves[from_to(&previous)])
+    }
+
+    // Sorts actions so that those most likely to cause a cutoff come first, each with its
+    // heuristic_action, which the search needs anyway.
+    pub fn order(
+        &self,
+        state: &State,
+        actions: Vec<Action>,
+        cached_action: Option<Action>,
+        ply: u32,
+        previous_action: Option<Action>,
+    ) -> Vec<(Action, i32)> {
+        let killers = self.killers.get(ply as usize).copied().unwrap_or([None; 2]);
+        let countermove = self.countermove(previous_action);
+
+        let mut scored: Vec<(Stage, i32, i32, Action)> = actions
+            .into_iter()
+            .map(|a| {
+                let (stage, score) = if Some(a) == cached_action {
+                    (Stage::CachedAction, 0)
+                } else if !is_quiet(&a) && a != Action::Tie {
+                    // Captures that lose material go after the quiet actions.
+                    match see(state, &a) {
+                        exchange if exchange < 0 => (Stage::LosingCapture, exchange),
+                        _ => (Stage::Capture, mvv_lva(state, &a)),
+                    }
+                } else if Some(a) == killers[0] {
+                    (Stage::Killer, 1)
+                } else if Some(a) == killers[1] {
+                    (Stage::Killer, 0)
+                } else if Some(a) == countermove {
+                    (Stage::Countermove, 0)
+                } else if a == Action::Tie {
+                    (Stage::Quiet, 0)
+                } else {
+                    (Stage::Quiet, self.history[Self::history_index(state, &a)])
+                };
+                (stage, score, heuristic_action(state, &a), a)
+            })
+            .collect();
+        scored.sort_by(|(stage_a, score_a, h_a, _), (stage_b, score_b, h_b, _)| {
+            (stage_b, score_b, h_b).cmp(&(stage_a, score_a, h_a))
+        });
+
+        scored.into_iter().map(|(_, _, h, a)| (a, h)).collect()
+    }
+
+    // Records that the quiet action caused a cutoff at the given depth, after the quiet actions
+    // in tried did not.
+    pub fn record_cutoff(
+        &mut self,
+        state: &State,
+        action: Action,
+        tried: &[Action],
+        depth_left: u32,
+        ply: u32,
+        previous_action: Option<Action>,
+    ) {
+        let ply = ply as usize;
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; 2]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(action) {
+            killers[1] = killers[0];
+            killers[0] = Some(action);
+        }
+
+        if let Some(previous) = previous_action.filter(|previous| *previous != Action::Tie) {
+            self.countermoves[from_to(&previous)] = Some(action);
+        }
+
+        // Bonuses shrink as a score approaches HISTORY_MAX, so that scores stay in range and
+        // recent results count for more.
+        let bonus = (depth_left * depth_left).min(MAX_HISTORY_BONUS as u32) as i32;
+        let mut update = |a: &Action, bonus: i32| {
+            let entry = &mut self.history[Self::history_index(state, a)];
+            *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+        };
+        update(&action, bonus);
+        for a in tried.iter().filter(|a| **a != action) {
+            update(a, -bonus);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::actions;
+
+    #[test]
+    fn stages() {
+        let state = State::from_fen("4k3/8/4p3/3p4/7n/2N5/8/R3K2Q w - - 0 1").unwrap();
+        let uci = |s: &str| Action::from_uci(&state, s).unwrap();
+        // Black's move before, Ke8-d7.
+        let previous = Some(Action::Jump {
+            s_y: 0,
+            s_x: 4,
+            e_y: 1,
+            e_x: 3,
+        });
+
+        let mut ordering = MoveOrdering::new();
+        ordering.record_cutoff(&state, uci("a1b1"), &[], 4, 3, None);
+        ordering.record_cutoff(&state, uci("e1d2"), &[], 4, 3, None);
+        ordering.record_cutoff(&state, uci("c3b5"), &[], 4, 5, previous);
+        ordering.record_cutoff(&state, uci("h1h2"), &[uci("a1a3")], 6, 7, None);
+
+        let ordered: Vec<Action> = ordering
+            .order(&state, actions(&state), Some(uci("a1a2")), 3, previous)
+            .into_iter()
+            .map(|(a, _)| a)
+            .collect();
+        let position = |s: &str| ordered.iter().position(|a| *a == uci(s)).unwrap();
+
+        let expected_start = ["a1a2", "h1h4", "e1d2", "a1b1", "c3b5", "h1h2"];
+        for (i, s) in expected_start.iter().enumerate() {
+            assert_eq!(position(s), i, "{}", s);
+        }
+        // Captures that lose material come last, the least losing first, after every quiet
+        // action, the one that failed to cause a cutoff last of all.
+        let n = ordered.len();
+        assert_eq!(position("c3d5"), n - 2);
+        assert_eq!(position("h1d5"), n - 1);
+        assert_eq!(position("a1a3"), n - 3);
+
+        // Without the previous action there is no countermove, and killers are kept per ply.
+        let ordered = ordering.order(&state, actions(&state), None, 4, None);
+        assert_eq!(ordered[0].0, uci("h1h4"));
+        assert_eq!(ordered[1].0, uci("h1h2"));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::action::Action;
+use crate::actions::actions;
+use crate::result::{inplace_result, inplace_revert};
+use crate::state::State;
+
+// Draw claims are not moves on the board, so they are left out of the counts.
+fn board_actions(state: &State) -> Vec<Action> {
+    actions(state)
+        .into_iter()
+        .filter(|a| *a != Action::Tie)
+        .collect()
+}
+
+fn count_leaves(state: &mut State, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let current_actions = board_actions(state);
+    if depth == 1 {
+        return current_actions.len() as u64;
+    }
+
+    let mut leaves = 0;
+    for a in current_actions {
+        let undo = inplace_result(state, &a);
+        leaves += count_leaves(state, depth - 1);
+        inplace_revert(state, undo);
+    }
+
+    leaves
+}
+
+// Counts the positions reachable from state in exactly depth moves.
+pub fn perft(state: &State, depth: u32) -> u64 {
+    count_leaves(&mut state.clone(), depth)
+}
+
+// Same as perft, but split up by the first move, which helps to find where a move generator
+// disagrees with a reference implementation.
+pub fn divide(state: &State, depth: u32) -> Vec<(Action, u64)> {
+    if depth == 0 {
+        return vec![];
+    }
+
+    let mut state = state.clone();
+    board_actions(&state)
+        .into_iter()
+        .map(|a| {
+            let undo = inplace_result(&mut state, &a);
+            let leaves = count_leaves(&mut state, depth - 1);
+            inplace_revert(&mut state, undo);
+            (a, leaves)
+        })
+        .collect()
+}
@@ -0,0 +1,797 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::action::Action;
+use crate::actions::validate_action;
+use crate::fen::{FenError, START_FEN};
+use crate::result::result;
+use crate::san::SanError;
+use crate::state::{State, PC};
+use crate::value::{value, GameResult, Termination};
+
+// Lines of movetext are wrapped before reaching this many characters.
+const MAX_LINE_LENGTH: usize = 80;
+
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+pub struct PgnMove {
+    pub action: Action,
+    // Commentary placed before the move, only used at the start of a game or a variation.
+    pub pre_comment: Option<String>,
+    pub comment: Option<String>,
+    pub nags: Vec<u8>,
+    // Alternatives to this move, each starting from the position before it.
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+pub struct Pgn {
+    pub tags: Vec<(String, String)>,
+    pub initial_state: State,
+    pub moves: Vec<PgnMove>,
+    pub result: GameResult,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PgnError {
+    UnterminatedTag(usize),
+    UnterminatedComment(usize),
+    UnexpectedCharacter {
+        line: usize,
+        c: char,
+    },
+    UnbalancedVariation(usize),
+    MisplacedToken {
+        line: usize,
+        token: String,
+    },
+    InvalidFen(FenError),
+    IllegalMove {
+        line: usize,
+        san: String,
+        error: SanError,
+    },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::UnterminatedTag(line) => write!(f, "line {}: unterminated tag pair", line),
+            PgnError::UnterminatedComment(line) => {
+                write!(f, "line {}: unterminated comment", line)
+            }
+            PgnError::UnexpectedCharacter { line, c } => {
+                write!(f, "line {}: unexpected character '{}'", line, c)
+            }
+            PgnError::UnbalancedVariation(line) => {
+                write!(f, "line {}: unbalanced variation parentheses", line)
+            }
+            PgnError::MisplacedToken { line, token } => {
+                write!(f, "line {}: '{}' is not allowed here", line, token)
+            }
+            PgnError::InvalidFen(e) => write!(f, "invalid FEN tag: {}", e),
+            PgnError::IllegalMove { line, error, .. } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl Error for PgnError {}
+
+pub fn result_token(result: GameResult) -> &'static str {
+    match result {
+        GameResult::Running => "*",
+        GameResult::WhiteWin(_) => "1-0",
+        GameResult::BlackWin(_) => "0-1",
+        GameResult::Tie(_) => "1/2-1/2",
+    }
+}
+
+// The result token alone does not say how the game ended; see infer_termination.
+fn token_to_result(token: &str) -> Option<GameResult> {
+    match token {
+        "*" => Some(GameResult::Running),
+        "1-0" => Some(GameResult::WhiteWin(Termination::Unknown)),
+        "0-1" => Some(GameResult::BlackWin(Termination::Unknown)),
+        "1/2-1/2" => Some(GameResult::Tie(Termination::Unknown)),
+        _ => None,
+    }
+}
+
+// Value of the standard Termination tag; the more specific reasons have no tag value of their own.
+fn termination_tag(termination: Termination) -> Option<&'static str> {
+    match termination {
+        Termination::Timeout => Some("time forfeit"),
+        Termination::Unknown => None,
+        _ => Some("normal"),
+    }
+}
+
+// Recovers the reason for a result read from a PGN file, either from the Termination tag or from
+// the final position of the main line.
+fn infer_termination(pgn: &Pgn) -> GameResult {
+    let reached = match pgn.states().last() {
+        Some(final_state) => value(final_state),
+        None => GameResult::Running,
+    };
+    let same_outcome = std::mem::discriminant(&reached) == std::mem::discriminant(&pgn.result);
+
+    match pgn.result {
+        GameResult::Running => GameResult::Running,
+        _ if same_outcome => reached,
+        GameResult::WhiteWin(_) if pgn.tag("Termination") == Some("time forfeit") => {
+            GameResult::WhiteWin(Termination::Timeout)
+        }
+        GameResult::BlackWin(_) if pgn.tag("Termination") == Some("time forfeit") => {
+            GameResult::BlackWin(Termination::Timeout)
+        }
+        result => result,
+    }
+}
+
+// Maps the traditional suffix annotations onto their numeric annotation glyphs.
+fn suffix_to_nag(suffix: &str) -> Option<u8> {
+    match suffix {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    }
+}
+
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    OpenVariation,
+    CloseVariation,
+    GameResult(GameResult),
+    Move(String),
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_+#=:-/.*".contains(c)
+}
+
+fn read_tag(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    line: usize,
+) -> Result<Token, PgnError> {
+    let name: String = {
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() && name.is_empty() {
+                chars.next();
+            } else if c.is_ascii_alphanumeric() || c == '_' {
+                name.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        name
+    };
+    while let Some(&c) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+    if name.is_empty() || chars.next() != Some('"') {
+        return Err(PgnError::UnterminatedTag(line));
+    }
+
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(c) => value.push(c),
+                None => return Err(PgnError::UnterminatedTag(line)),
+            },
+            Some('"') => break,
+            Some('\n') | None => return Err(PgnError::UnterminatedTag(line)),
+            Some(c) => value.push(c),
+        }
+    }
+    loop {
+        match chars.next() {
+            Some(']') => return Ok(Token::Tag(name, value)),
+            Some(c) if c.is_whitespace() && c != '\n' => (),
+            _ => return Err(PgnError::UnterminatedTag(line)),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, PgnError> {
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut at_line_start = true;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token_line = line;
+        match c {
+            '\n' => {
+                line += 1;
+                at_line_start = true;
+                continue;
+            }
+            // Escaped lines are reserved for other programs and skipped entirely.
+            '%' if at_line_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '[' => tokens.push((read_tag(&mut chars, line)?, token_line)),
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            comment.push(c);
+                        }
+                        None => return Err(PgnError::UnterminatedComment(token_line)),
+                    }
+                }
+                let comment = comment.split_whitespace().collect::<Vec<_>>().join(" ");
+                tokens.push((Token::Comment(comment), token_line));
+            }
+            ';' => {
+                let mut comment = String::new();
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                    comment.push(c);
+                }
+                tokens.push((Token::Comment(comment.trim().to_string()), token_line));
+            }
+            '(' => tokens.push((Token::OpenVariation, token_line)),
+            ')' => tokens.push((Token::CloseVariation, token_line)),
+            '$' => {
+                let mut digits = String::new();
+                while let Some(&d) = chars.peek() {
+                    if !d.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(d);
+                    chars.next();
+                }
+                match digits.parse::<u8>() {
+                    Ok(nag) => tokens.push((Token::Nag(nag), token_line)),
+                    Err(_) => return Err(PgnError::UnexpectedCharacter { line, c }),
+                }
+            }
+            '!' | '?' => {
+                let mut suffix = c.to_string();
+                while let Some(&s) = chars.peek() {
+                    if s != '!' && s != '?' {
+                        break;
+                    }
+                    suffix.push(s);
+                    chars.next();
+                }
+                match suffix_to_nag(&suffix) {
+                    Some(nag) => tokens.push((Token::Nag(nag), token_line)),
+                    None => {
+                        return Err(PgnError::MisplacedToken {
+                            line,
+                            token: suffix,
+                        })
+                    }
+                }
+            }
+            c if is_symbol_char(c) => {
+                let mut symbol = c.to_string();
+                while let Some(&s) = chars.peek() {
+                    if !is_symbol_char(s) {
+                        break;
+                    }
+                    symbol.push(s);
+                    chars.next();
+                }
+
+                if let Some(result) = token_to_result(&symbol) {
+                    tokens.push((Token::GameResult(result), token_line));
+                    continue;
+                }
+
+                // Move number indications such as "12." or "12..." may be glued to the move.
+                let without_number = match symbol.find('.') {
+                    Some(i) if symbol[..i].chars().all(|c| c.is_ascii_digit()) => {
+                        symbol[i..].trim_start_matches('.')
+                    }
+                    _ => &symbol[..],
+                };
+                if !without_number.is_empty() {
+                    tokens.push((Token::Move(without_number.to_string()), token_line));
+                }
+            }
+            c => return Err(PgnError::UnexpectedCharacter { line, c }),
+        }
+        at_line_start = false;
+    }
+
+    Ok(tokens)
+}
+
+fn append_comment(target: &mut Option<String>, comment: &str) {
+    match target {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(comment);
+        }
+        None => *target = Some(comment.to_string()),
+    }
+}
+
+// Parses a sequence of moves starting at `state`, recursing into variations. At the top level
+// this stops after the game result; inside a variation it stops after the closing parenthesis.
+fn parse_line(
+    tokens: &[(Token, usize)],
+    pos: &mut usize,
+    mut state: State,
+    in_variation: bool,
+) -> Result<(Vec<PgnMove>, Option<GameResult>), PgnError> {
+    let mut moves: Vec<PgnMove> = vec![];
+    let mut state_before_last_move = None;
+    let mut pending_comment = None;
+
+    while let Some((token, line)) = tokens.get(*pos) {
+        let line = *line;
+        match token {
+            Token::Tag(..) if !in_variation => return Ok((moves, None)),
+            Token::Tag(name, _) => {
+                return Err(PgnError::MisplacedToken {
+                    line,
+                    token: name.clone(),
+                })
+            }
+            Token::Comment(comment) => match moves.last_mut() {
+                Some(last) => append_comment(&mut last.comment, comment),
+                None => append_comment(&mut pending_comment, comment),
+            },
+            Token::Nag(nag) => match moves.last_mut() {
+                Some(last) => last.nags.push(*nag),
+                None => {
+                    return Err(PgnError::MisplacedToken {
+                        line,
+                        token: format!("${}", nag),
+                    })
+                }
+            },
+            Token::OpenVariation => {
+                let variation_state = match &state_before_last_move {
+                    Some(s) => State::clone(s),
+                    None => {
+                        return Err(PgnError::MisplacedToken {
+                            line,
+                            token: "(".to_string(),
+                        })
+                    }
+                };
+                *pos += 1;
+                let (variation, _) = parse_line(tokens, pos, variation_state, true)?;
+                if let Some(last) = moves.last_mut() {
+                    last.variations.push(variation);
+                }
+                continue;
+            }
+            Token::CloseVariation if in_variation => {
+                *pos += 1;
+                return Ok((moves, None));
+            }
+            Token::CloseVariation => return Err(PgnError::UnbalancedVariation(line)),
+            Token::GameResult(_) if in_variation => {
+                return Err(PgnError::UnbalancedVariation(line))
+            }
+            Token::GameResult(result) => {
+                *pos += 1;
+                return Ok((moves, Some(*result)));
+            }
+            Token::Move(san) => {
+                let illegal = |error| PgnError::IllegalMove {
+                    line,
+                    san: san.clone(),
+                    error,
+                };
+                let action = Action::from_san(&state, san).map_err(illegal)?;
+                if !validate_action(&state, &action) {
+                    return Err(illegal(SanError::Illegal(san.clone())));
+                }
+
+                let next_state = result(&state, &action);
+                moves.push(PgnMove {
+                    action,
+                    pre_comment: pending_comment.take(),
+                    comment: None,
+                    nags: vec![],
+                    variations: vec![],
+                });
+                state_before_last_move = Some(state);
+                state = next_state;
+            }
+        }
+        *pos += 1;
+    }
+
+    if in_variation {
+        let line = tokens.last().map_or(1, |(_, line)| *line);
+        return Err(PgnError::UnbalancedVariation(line));
+    }
+
+    Ok((moves, None))
+}
+
+// Parses every game in a PGN database.
+pub fn parse_pgn(text: &str) -> Result<Vec<Pgn>, PgnError> {
+    let tokens = tokenize(text)?;
+    let mut games = vec![];
+    let mut pos = 0;
+
+    while pos < tokens.len() {
+        let mut tags = vec![];
+        while let Some((Token::Tag(name, value), _)) = tokens.get(pos) {
+            tags.push((name.clone(), value.clone()));
+            pos += 1;
+        }
+
+        let initial_state = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => State::from_fen(fen).map_err(PgnError::InvalidFen)?,
+            None => State::new(),
+        };
+
+        let (moves, result) = parse_line(&tokens, &mut pos, initial_state.clone(), false)?;
+        let result = result
+            .or_else(|| {
+                tags.iter()
+                    .find(|(name, _)| name == "Result")
+                    .and_then(|(_, value)| token_to_result(value))
+            })
+            .unwrap_or(GameResult::Running);
+
+        let mut game = Pgn {
+            tags,
+            initial_state,
+            moves,
+            result,
+        };
+        game.result = infer_termination(&game);
+        games.push(game);
+    }
+
+    Ok(games)
+}
+
+fn escape_tag_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Pgn {
+    pub fn new(initial_state: State) -> Self {
+        // Unknown values are "?", except for the date, which keeps its format.
+        let mut tags: Vec<(String, String)> = SEVEN_TAG_ROSTER
+            .iter()
+            .map(|&name| {
+                let unknown = if name == "Date" { "????.??.??" } else { "?" };
+                (name.to_string(), unknown.to_string())
+            })
+            .collect();
+
+        if initial_state.to_fen() != START_FEN {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), initial_state.to_fen()));
+        }
+
+        let mut pgn = Pgn {
+            tags,
+            initial_state,
+            moves: vec![],
+            result: GameResult::Running,
+        };
+        pgn.set_result(GameResult::Running);
+
+        pgn
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: GameResult) {
+        self.result = result;
+        self.set_tag("Result", result_token(result));
+        if let Some(termination) = result.termination().and_then(termination_tag) {
+            self.set_tag("Termination", termination);
+        }
+    }
+
+    pub fn push_move(&mut self, action: Action, comment: Option<String>) {
+        self.moves.push(PgnMove {
+            action,
+            pre_comment: None,
+            comment,
+            nags: vec![],
+            variations: vec![],
+        });
+    }
+
+    // The positions along the main line, starting with the initial state.
+    pub fn states(&self) -> Vec<State> {
+        let mut states = vec![self.initial_state.clone()];
+        for pgn_move in &self.moves {
+            let next_state = result(&states[states.len() - 1], &pgn_move.action);
+            states.push(next_state);
+        }
+
+        states
+    }
+
+    // Splits the game into the tokens of its movetext, including the final result token.
+    fn movetext_tokens(&self) -> Vec<String> {
+        let mut tokens = vec![];
+        line_tokens(&self.moves, self.initial_state.clone(), &mut tokens);
+        tokens.push(result_token(self.result).to_string());
+        tokens
+    }
+}
+
+fn comment_token(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('}', ")"))
+}
+
+fn line_tokens(moves: &[PgnMove], mut state: State, tokens: &mut Vec<String>) {
+    let mut needs_move_number = true;
+
+    for pgn_move in moves {
+        // Draw claims end the game and are only reflected in the result token.
+        if pgn_move.action == Action::Tie {
+            break;
+        }
+
+        if let Some(comment) = &pgn_move.pre_comment {
+            tokens.push(comment_token(comment));
+        }
+
+        let move_number = state.moves / 2 + 1;
+        if state.turn == PC::White {
+            tokens.push(format!("{}.", move_number));
+        } else if needs_move_number {
+            tokens.push(format!("{}...", move_number));
+        }
+        tokens.push(pgn_move.action.to_san(&state));
+        for nag in &pgn_move.nags {
+            tokens.push(format!("${}", nag));
+        }
+
+        needs_move_number = false;
+        if let Some(comment) = &pgn_move.comment {
+            tokens.push(comment_token(comment));
+            needs_move_number = true;
+        }
+        for variation in &pgn_move.variations {
+            tokens.push("(".to_string());
+            line_tokens(variation, state.clone(), tokens);
+            tokens.push(")".to_string());
+            needs_move_number = true;
+        }
+
+        state = result(&state, &pgn_move.action);
+    }
+}
+
+impl fmt::Display for Pgn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, escape_tag_value(value))?;
+        }
+        writeln!(f)?;
+
+        let mut line = String::new();
+        let mut previous_token = String::new();
+        for token in self.movetext_tokens() {
+            let separator = if line.is_empty() || previous_token == "(" || token == ")" {
+                ""
+            } else {
+                " "
+            };
+            if !line.is_empty() && line.len() + separator.len() + token.len() >= MAX_LINE_LENGTH {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            } else {
+                line.push_str(separator);
+            }
+            line.push_str(&token);
+            previous_token = token;
+        }
+        writeln!(f, "{}", line)?;
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays the moves given in SAN from state and records them in a new Pgn.
+    fn game_from_san(state: State, sans: &[&str]) -> Pgn {
+        let mut pgn = Pgn::new(state.clone());
+        let mut state = state;
+        for san in sans {
+            let action = Action::from_san(&state, san).unwrap();
+            state = result(&state, &action);
+            pgn.push_move(action, None);
+        }
+        pgn
+    }
+
+    fn actions_of(moves: &[PgnMove]) -> Vec<Action> {
+        moves.iter().map(|m| m.action).collect()
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let mut pgn = game_from_san(
+            State::new(),
+            &["e4", "e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"],
+        );
+        pgn.set_tag("White", "Scholar");
+        pgn.set_tag("Event", "A \"quoted\" event");
+        pgn.moves[5].comment = Some("a blunder".to_string());
+        pgn.set_result(GameResult::WhiteWin(Termination::Checkmate));
+
+        let text = pgn.to_string();
+        assert!(text.contains("[Event \"A \\\"quoted\\\" event\"]"));
+        assert!(text.contains("[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]"));
+        assert!(text.contains("3. Qh5 Nf6 {a blunder} 4. Qxf7# 1-0"));
+
+        let games = parse_pgn(&text).unwrap();
+        assert_eq!(games.len(), 1);
+        let parsed = &games[0];
+        assert_eq!(parsed.tags, pgn.tags);
+        assert_eq!(actions_of(&parsed.moves), actions_of(&pgn.moves));
+        assert_eq!(parsed.moves[5].comment.as_deref(), Some("a blunder"));
+        assert_eq!(parsed.result, GameResult::WhiteWin(Termination::Checkmate));
+    }
+
+    #[test]
+    fn export_from_fen_with_black_to_move() {
+        let state = State::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 12").unwrap();
+        let pgn = game_from_san(state.clone(), &["Kd7", "e4"]);
+
+        let text = pgn.to_string();
+        assert!(text.contains("[SetUp \"1\"]"));
+        assert!(text.contains(&format!("[FEN \"{}\"]", state.to_fen())));
+        assert!(text.contains("12... Kd7 13. e4 *"));
+
+        let parsed = parse_pgn(&text).unwrap().swap_remove(0);
+        assert_eq!(parsed.initial_state, state);
+        assert_eq!(actions_of(&parsed.moves), actions_of(&pgn.moves));
+        assert_eq!(parsed.result, GameResult::Running);
+    }
+
+    #[test]
+    fn export_wraps_long_movetext() {
+        let shuffle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+        let sans: Vec<&str> = shuffle.iter().cycle().take(40).copied().collect();
+        let pgn = game_from_san(State::new(), &sans);
+
+        let text = pgn.to_string();
+        assert!(text.lines().all(|line| line.len() < MAX_LINE_LENGTH));
+        let parsed = parse_pgn(&text).unwrap().swap_remove(0);
+        assert_eq!(actions_of(&parsed.moves), actions_of(&pgn.moves));
+    }
+
+    #[test]
+    fn import_variations_and_nags() {
+        let text = "[Event \"Test\"]\n\n\
+            {Opening} 1. e4 e5 (1... c5 $14 2. Nf3 (2. c3) d6) 2. Nf3!? {Developing} Nc6?? \
+            ; rest of line\n3. Bb5 *\n";
+        let game = parse_pgn(text).unwrap().swap_remove(0);
+
+        assert_eq!(game.tag("Event"), Some("Test"));
+        assert_eq!(game.moves.len(), 5);
+        assert_eq!(game.moves[0].pre_comment.as_deref(), Some("Opening"));
+        assert_eq!(game.moves[2].nags, vec![5]);
+        assert_eq!(game.moves[2].comment.as_deref(), Some("Developing"));
+        assert_eq!(game.moves[3].nags, vec![4]);
+        assert_eq!(game.moves[3].comment.as_deref(), Some("rest of line"));
+
+        // The variation replaces 1... e5 and holds a variation of its own.
+        let variation = &game.moves[1].variations[0];
+        assert_eq!(variation.len(), 3);
+        assert_eq!(variation[0].action.to_san(&game.states()[1]), "c5");
+        assert_eq!(variation[0].nags, vec![14]);
+        assert_eq!(variation[1].variations[0].len(), 1);
+
+        // Writing the game back out keeps the variations and annotations.
+        let reparsed = parse_pgn(&game.to_string()).unwrap().swap_remove(0);
+        assert_eq!(actions_of(&reparsed.moves), actions_of(&game.moves));
+        let reparsed_variation = &reparsed.moves[1].variations[0];
+        assert_eq!(actions_of(reparsed_variation), actions_of(variation));
+        assert_eq!(reparsed_variation[0].nags, vec![14]);
+        assert_eq!(reparsed.moves[2].nags, vec![5]);
+    }
+
+    #[test]
+    fn import_several_games() {
+        let text = "[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n\n\
+            [FEN \"4k3/8/8/8/8/8/8/R3K3 w Q - 0 1\"]\n\n1. O-O-O 1/2-1/2\n";
+        let games = parse_pgn(text).unwrap();
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(
+            games[0].result,
+            GameResult::BlackWin(Termination::Checkmate)
+        );
+        assert_eq!(games[1].moves.len(), 1);
+        assert_eq!(games[1].result, GameResult::Tie(Termination::Unknown));
+        assert_eq!(
+            games[1].states()[1].to_fen(),
+            "4k3/8/8/8/8/8/8/2KR4 b - - 1 1"
+        );
+    }
+
+    #[test]
+    fn import_errors() {
+        let error = |text: &str| parse_pgn(text).err().unwrap();
+
+        assert_eq!(error("[Event \"x]\n1. e4 *"), PgnError::UnterminatedTag(1));
+        assert_eq!(
+            error("1. e4 {never\nclosed *"),
+            PgnError::UnterminatedComment(1)
+        );
+        assert_eq!(
+            error("1. e4 e5\n(1... c5 2. Nf3 *"),
+            PgnError::UnbalancedVariation(2)
+        );
+        assert_eq!(
+            error("1. e4 e5 (1... c5 2. Nf3"),
+            PgnError::UnbalancedVariation(1)
+        );
+        assert_eq!(error("1. e4 ) e5 *"), PgnError::UnbalancedVariation(1));
+        assert_eq!(
+            error("( 1. e4 ) *"),
+            PgnError::MisplacedToken {
+                line: 1,
+                token: "(".to_string()
+            }
+        );
+        assert_eq!(
+            error("$1 1. e4 *"),
+            PgnError::MisplacedToken {
+                line: 1,
+                token: "$1".to_string()
+            }
+        );
+        assert!(matches!(
+            error("1. e4 e5\n2. Ke3 *"),
+            PgnError::IllegalMove { line: 2, ref san, .. } if san == "Ke3"
+        ));
+        assert!(matches!(
+            error("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n*"),
+            PgnError::InvalidFen(_)
+        ));
+    }
+}
@@ -1,24 +1,62 @@
 use crate::action::Action;
 use crate::state::{Piece, State, PT};
+use crate::value::{claimed_draw, Termination};
+use crate::zobrist::{black_to_move_key, rights_key};
+
+// Everything inplace_revert needs to undo an inplace_result call.
+pub struct Undo {
+    moved_pieces: Vec<(usize, usize, Option<Piece>)>,
+    halfmove_clock: usize,
+    hash_key: u64,
+}
 
-pub fn inplace_result(
-    mut state: &mut State,
-    action: &Action,
-) -> Vec<(usize, usize, Option<Piece>)> {
-    let mut moved_pieces = Vec::new();
+fn resets_halfmove_clock(state: &State, action: &Action) -> bool {
+    match *action {
+        Action::Capture { .. } | Action::Promotion { .. } | Action::Enpassant { .. } => true,
+        Action::Jump { s_y, s_x, .. } => matches!(
+            state.board[s_y][s_x],
+            Some(Piece {
+                t: PT::Pawn { .. },
+                ..
+            })
+        ),
+        Action::Castling { .. } | Action::Tie => false,
+    }
+}
+
+fn updated_halfmove_clock(state: &State, action: &Action) -> usize {
+    if *action == Action::Tie {
+        state.halfmove_clock
+    } else if resets_halfmove_clock(state, action) {
+        0
+    } else {
+        state.halfmove_clock + 1
+    }
+}
 
-    fn piece_with_updated_piece_state(state: &mut State, e_y: usize, e_x: usize, s_y: usize) {
-        match state.board[e_y][e_x].as_mut() {
-            None => (),
-            Some(p) => match p.t {
+pub fn inplace_result(state: &mut State, action: &Action) -> Undo {
+    let mut moved_pieces = Vec::new();
+    let halfmove_clock = state.halfmove_clock;
+    let hash_key = state.hash_key;
+    // What a draw is claimed for depends on the position the claim is made in.
+    if *action == Action::Tie {
+        state.drawn = Some(claimed_draw(state));
+    }
+    state.halfmove_clock = updated_halfmove_clock(state, action);
+    // set_square takes care of the piece keys; castling and en-passant rights can change with any
+    // move, so their keys are swapped out around it.
+    state.hash_key ^= rights_key(state);
+
+    // Moves the piece on (s_y, s_x) to (e_y, e_x), updating its en passant and castling state.
+    fn move_piece(state: &mut State, s_y: usize, s_x: usize, e_y: usize, e_x: usize) {
+        let updated_piece = state.board[s_y][s_x].map(|mut p| {
+            match p.t {
                 PT::Pawn { ref mut last_jump } => {
-                    let updated_last_jump = if s_y == e_y + 2 || e_y == s_y + 2 {
+                    *last_jump = if s_y == e_y + 2 || e_y == s_y + 2 {
                         Some(state.moves)
                     } else {
                         None
                     };
-
-                    *last_jump = updated_last_jump;
                 }
                 PT::Rook { ref mut has_moved } => {
                     *has_moved = true;
@@ -27,8 +65,12 @@ pub fn inplace_result(
                     *has_moved = true;
                 }
                 _ => (),
-            },
-        }
+            }
+            p
+        });
+
+        state.set_square(s_y, s_x, None);
+        state.set_square(e_y, e_x, updated_piece);
     }
 
     match *action {
@@ -36,50 +78,28 @@ pub fn inplace_result(
             moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
             moved_pieces.push((e_y, e_x, None));
 
-            state.board[e_y][e_x] = state.board[s_y][s_x];
-            piece_with_updated_piece_state(state, e_y, e_x, s_y);
-            state.board[s_y][s_x] = None;
+            move_piece(state, s_y, s_x, e_y, e_x);
         }
         Action::Capture { s_y, s_x, e_y, e_x } => {
             moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
             moved_pieces.push((e_y, e_x, state.board[e_y][e_x]));
 
-            state.board[e_y][e_x] = state.board[s_y][s_x];
-            piece_with_updated_piece_state(state, e_y, e_x, s_y);
-            state.board[s_y][s_x] = None;
+            move_piece(state, s_y, s_x, e_y, e_x);
         }
         Action::Castling {
             s_y,
             s_x,
             queenside,
         } => {
-            if queenside {
-                moved_pieces.push((s_y, 2, state.board[s_y][2]));
-                moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
-                moved_pieces.push((s_y, 3, state.board[s_y][3]));
-                moved_pieces.push((s_y, 0, state.board[s_y][0]));
-
-                state.board[s_y][2] = state.board[s_y][s_x];
-                piece_with_updated_piece_state(state, s_y, 2, s_y);
-                state.board[s_y][s_x] = None;
-
-                state.board[s_y][3] = state.board[s_y][0];
-                piece_with_updated_piece_state(state, s_y, 3, s_y);
-                state.board[s_y][0] = None;
-            } else {
-                moved_pieces.push((s_y, 6, state.board[s_y][6]));
-                moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
-                moved_pieces.push((s_y, 5, state.board[s_y][5]));
-                moved_pieces.push((s_y, 7, state.board[s_y][7]));
+            let (king_x, rook_s_x, rook_e_x) = if queenside { (2, 0, 3) } else { (6, 7, 5) };
 
-                state.board[s_y][6] = state.board[s_y][s_x];
-                piece_with_updated_piece_state(state, s_y, 6, s_y);
-                state.board[s_y][s_x] = None;
+            moved_pieces.push((s_y, king_x, state.board[s_y][king_x]));
+            moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
+            moved_pieces.push((s_y, rook_e_x, state.board[s_y][rook_e_x]));
+            moved_pieces.push((s_y, rook_s_x, state.board[s_y][rook_s_x]));
 
-                state.board[s_y][5] = state.board[s_y][7];
-                piece_with_updated_piece_state(state, s_y, 5, s_y);
-                state.board[s_y][7] = None;
-            }
+            move_piece(state, s_y, s_x, s_y, king_x);
+            move_piece(state, s_y, rook_s_x, s_y, rook_e_x);
         }
         Action::Promotion {
             s_y,
@@ -91,39 +111,43 @@ pub fn inplace_result(
             moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
             moved_pieces.push((e_y, e_x, state.board[e_y][e_x]));
 
-            state.board[e_y][e_x] = Some(Piece {
+            let promoted_piece = Piece {
                 c: state.turn,
                 t: to_piece,
-            });
-            state.board[s_y][s_x] = None;
+            };
+            state.set_square(e_y, e_x, Some(promoted_piece));
+            state.set_square(s_y, s_x, None);
         }
         Action::Enpassant { s_y, s_x, e_y, e_x } => {
             moved_pieces.push((s_y, s_x, state.board[s_y][s_x]));
             moved_pieces.push((e_y, e_x, state.board[e_y][e_x]));
             moved_pieces.push((s_y, e_x, state.board[s_y][e_x]));
 
-            state.board[e_y][e_x] = state.board[s_y][s_x];
-            piece_with_updated_piece_state(state, e_y, e_x, s_y);
-            state.board[s_y][s_x] = None;
-            state.board[s_y][e_x] = None;
+            move_piece(state, s_y, s_x, e_y, e_x);
+            state.set_square(s_y, e_x, None);
         }
-        Action::Tie => state.drawn = true,
+        Action::Tie => (),
     };
 
     state.turn = state.turn.opponent();
     state.moves += 1;
+    state.hash_key ^= black_to_move_key() ^ rights_key(state);
 
     let v = state.hash_to_occurences.entry(state.hash()).or_insert(0);
 
     *v += 1;
-    if *v >= 5 {
-        state.drawn = true;
+    if *v >= 5 && state.drawn.is_none() {
+        state.drawn = Some(Termination::FivefoldRepetition);
     }
 
-    moved_pieces
+    Undo {
+        moved_pieces,
+        halfmove_clock,
+        hash_key,
+    }
 }
 
-pub fn inplace_revert(mut state: &mut State, moved_pieces: Vec<(usize, usize, Option<Piece>)>) {
+pub fn inplace_revert(state: &mut State, undo: Undo) {
     match state.hash_to_occurences.remove(&state.hash()) {
         None | Some(1) => (),
         Some(x) => {
@@ -131,132 +155,48 @@ pub fn inplace_revert(mut state: &mut State, moved_pieces: Vec<(usize, usize, Op
         }
     };
 
-    for (y, x, p) in moved_pieces {
-        state.board[y][x] = p;
+    for (y, x, p) in undo.moved_pieces {
+        state.set_square(y, x, p);
     }
 
-    state.drawn = false;
+    state.halfmove_clock = undo.halfmove_clock;
+    state.hash_key = undo.hash_key;
+    state.drawn = None;
     state.turn = state.turn.opponent();
     state.moves -= 1;
 }
 
-// Note: this function doesn't check if the action is valid.
-// Applying the action when it is invalid leads to undefined behavior.
-pub fn result(old_state: &State, action: &Action) -> State {
-    let mut new_state = old_state.clone();
-
-    new_state.turn = new_state.turn.opponent();
-    new_state.moves += 1;
-
-    let piece_with_updated_piece_state =
-        |state: &mut State, e_y: usize, e_x: usize, s_y: usize| match &state.board[e_y][e_x] {
-            Some(Piece {
-                t: PT::Pawn { .. },
-                c,
-            }) => {
-                let last_jump = if e_y == s_y + 2 || s_y == e_y + 2 {
-                    Some(old_state.moves)
-                } else {
-                    None
-                };
-
-                Some(Piece {
-                    t: PT::Pawn { last_jump },
-                    c: *c,
-                })
-            }
-            Some(Piece {
-                t: PT::Rook { .. },
-                c,
-            }) => Some(Piece {
-                t: PT::Rook { has_moved: true },
-                c: *c,
-            }),
-            Some(Piece {
-                t: PT::King { .. },
-                c,
-            }) => Some(Piece {
-                t: PT::King { has_moved: true },
-                c: *c,
-            }),
-            Some(Piece { t, c }) => Some(Piece { t: *t, c: *c }),
-            None => None,
-        };
-
-    match *action {
-        Action::Jump { s_y, s_x, e_y, e_x } => {
-            new_state.board[e_y][e_x] = new_state.board[s_y][s_x];
-            new_state.board[e_y][e_x] =
-                piece_with_updated_piece_state(&mut new_state, e_y, e_x, s_y);
-            new_state.board[s_y][s_x] = None;
-        }
-        Action::Capture { s_y, s_x, e_y, e_x } => {
-            new_state.board[e_y][e_x] = new_state.board[s_y][s_x];
-            new_state.board[e_y][e_x] =
-                piece_with_updated_piece_state(&mut new_state, e_y, e_x, s_y);
-            new_state.board[s_y][s_x] = None;
-        }
-        Action::Castling {
-            s_y,
-            s_x,
-            queenside,
-        } => {
-            if queenside {
-                new_state.board[s_y][2] = new_state.board[s_y][s_x];
-                new_state.board[s_y][2] =
-                    piece_with_updated_piece_state(&mut new_state, s_y, 2, s_y);
-                new_state.board[s_y][s_x] = None;
+// Passes the turn without moving, which is not a legal move but lets the search ask whether the
+// position is still good for the side to move if it did nothing. The pawn that just jumped can no
+// longer be taken en passant. The position is not counted for repetitions.
+pub fn inplace_null_move(state: &mut State) -> Undo {
+    let undo = Undo {
+        moved_pieces: Vec::new(),
+        halfmove_clock: state.halfmove_clock,
+        hash_key: state.hash_key,
+    };
 
-                new_state.board[s_y][3] = new_state.board[s_y][0];
-                new_state.board[s_y][3] =
-                    piece_with_updated_piece_state(&mut new_state, s_y, 3, s_y);
-                new_state.board[s_y][0] = None;
-            } else {
-                new_state.board[s_y][6] = new_state.board[s_y][s_x];
-                new_state.board[s_y][6] =
-                    piece_with_updated_piece_state(&mut new_state, s_y, 6, s_y);
-                new_state.board[s_y][s_x] = None;
+    state.hash_key ^= rights_key(state);
+    state.halfmove_clock += 1;
+    state.turn = state.turn.opponent();
+    state.moves += 1;
+    state.hash_key ^= black_to_move_key() ^ rights_key(state);
 
-                new_state.board[s_y][5] = new_state.board[s_y][7];
-                new_state.board[s_y][5] =
-                    piece_with_updated_piece_state(&mut new_state, s_y, 5, s_y);
-                new_state.board[s_y][7] = None;
-            }
-        }
-        Action::Promotion {
-            s_y,
-            s_x,
-            e_y,
-            e_x,
-            to_piece,
-        } => {
-            new_state.board[e_y][e_x] = Some(Piece {
-                c: old_state.turn,
-                t: to_piece,
-            });
-            new_state.board[s_y][s_x] = None;
-        }
-        Action::Enpassant { s_y, s_x, e_y, e_x } => {
-            new_state.board[e_y][e_x] = new_state.board[s_y][s_x];
-            new_state.board[e_y][e_x] =
-                piece_with_updated_piece_state(&mut new_state, e_y, e_x, s_y);
-            new_state.board[s_y][s_x] = None;
-            new_state.board[s_y][e_x] = None;
-        }
-        Action::Tie => {
-            new_state.drawn = true;
-        }
-    };
+    undo
+}
 
-    let v = new_state
-        .hash_to_occurences
-        .entry(new_state.hash())
-        .or_insert(0);
+pub fn inplace_null_revert(state: &mut State, undo: Undo) {
+    state.halfmove_clock = undo.halfmove_clock;
+    state.hash_key = undo.hash_key;
+    state.turn = state.turn.opponent();
+    state.moves -= 1;
+}
 
-    *v += 1;
-    if *v >= 5 {
-        new_state.drawn = true;
-    }
+// Note: this function doesn't check if the action is valid.
+// Applying the action when it is invalid leads to undefined behavior.
+pub fn result(old_state: &State, action: &Action) -> State {
+    let mut new_state = old_state.clone();
+    inplace_result(&mut new_state, action);
 
     new_state
 }
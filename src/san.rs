@@ -0,0 +1,380 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::action::Action;
+use crate::actions::{actions, is_king_attacked};
+use crate::fen::{parse_square, square_to_string};
+use crate::result::result;
+use crate::state::{Piece, State, PT};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SanError {
+    Malformed(String),
+    Illegal(String),
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanError::Malformed(s) => write!(f, "'{}' is not a valid SAN move", s),
+            SanError::Illegal(s) => write!(f, "'{}' does not match any legal move", s),
+            SanError::Ambiguous(s) => write!(f, "'{}' matches more than one legal move", s),
+        }
+    }
+}
+
+impl Error for SanError {}
+
+fn piece_letter(pt: PT) -> Option<char> {
+    match pt {
+        PT::Pawn { .. } => None,
+        PT::Knight => Some('N'),
+        PT::Bishop => Some('B'),
+        PT::Rook { .. } => Some('R'),
+        PT::Queen => Some('Q'),
+        PT::King { .. } => Some('K'),
+    }
+}
+
+fn letter_to_piece_type(c: char) -> Option<PT> {
+    match c {
+        'N' => Some(PT::Knight),
+        'B' => Some(PT::Bishop),
+        'R' => Some(PT::Rook { has_moved: true }),
+        'Q' => Some(PT::Queen),
+        'K' => Some(PT::King { has_moved: true }),
+        _ => None,
+    }
+}
+
+fn same_piece_type(a: PT, b: PT) -> bool {
+    std::mem::discriminant(&a) == std::mem::discriminant(&b)
+}
+
+fn is_capture(state: &State, action: &Action) -> bool {
+    match *action {
+        Action::Capture { .. } | Action::Enpassant { .. } => true,
+        Action::Promotion { e_y, e_x, .. } => state.board[e_y][e_x].is_some(),
+        _ => false,
+    }
+}
+
+fn legal_moves(state: &State) -> Vec<Action> {
+    actions(state)
+        .into_iter()
+        .filter(|a| *a != Action::Tie)
+        .collect()
+}
+
+// Returns the "+" or "#" suffix for an action, or an empty string if it does not give check.
+fn check_suffix(state: &State, action: &Action) -> &'static str {
+    let after_state = result(state, action);
+    if !is_king_attacked(&after_state, after_state.turn) {
+        ""
+    } else if legal_moves(&after_state).is_empty() {
+        "#"
+    } else {
+        "+"
+    }
+}
+
+impl Action {
+    // Standard Algebraic Notation for the action, which must be legal in the given state.
+    pub fn to_san(&self, state: &State) -> String {
+        let main_piece = match *self {
+            Action::Tie => return "½-½".to_string(),
+            _ => self
+                .get_main_piece(state)
+                .expect("Invalid action found in to_san"),
+        };
+
+        let mut san = match *self {
+            Action::Castling {
+                queenside: true, ..
+            } => "O-O-O".to_string(),
+            Action::Castling {
+                queenside: false, ..
+            } => "O-O".to_string(),
+            _ => {
+                let (s_y, s_x) = self.get_main_coords();
+                let (e_y, e_x) = self.get_end_coords();
+                let mut san = String::new();
+
+                match piece_letter(main_piece.t) {
+                    Some(letter) => {
+                        san.push(letter);
+
+                        let rivals: Vec<(usize, usize)> = legal_moves(state)
+                            .iter()
+                            .filter(|a| {
+                                a.get_end_coords() == (e_y, e_x)
+                                    && a.get_main_coords() != (s_y, s_x)
+                                    && a.get_main_piece(state)
+                                        .is_some_and(|p| same_piece_type(p.t, main_piece.t))
+                            })
+                            .map(|a| a.get_main_coords())
+                            .collect();
+
+                        if !rivals.is_empty() {
+                            let file = square_to_string(s_y, s_x);
+                            if rivals.iter().all(|&(_, x)| x != s_x) {
+                                san.push_str(&file[..1]);
+                            } else if rivals.iter().all(|&(y, _)| y != s_y) {
+                                san.push_str(&file[1..]);
+                            } else {
+                                san.push_str(&file);
+                            }
+                        }
+                    }
+                    None => {
+                        if is_capture(state, self) {
+                            san.push_str(&square_to_string(s_y, s_x)[..1]);
+                        }
+                    }
+                }
+
+                if is_capture(state, self) {
+                    san.push('x');
+                }
+                san.push_str(&square_to_string(e_y, e_x));
+
+                if let Action::Promotion { to_piece, .. } = *self {
+                    san.push('=');
+                    san.push(piece_letter(to_piece).expect("Invalid promotion piece"));
+                }
+
+                san
+            }
+        };
+
+        san.push_str(check_suffix(state, self));
+        san
+    }
+
+    // Resolves a SAN string to the single legal action it describes. Check, mate and annotation
+    // suffixes are ignored, and so are redundant disambiguations and missing capture markers.
+    pub fn from_san(state: &State, san: &str) -> Result<Action, SanError> {
+        let malformed = || SanError::Malformed(san.to_string());
+
+        let mut body = san.trim();
+        if let Some(stripped) = body.strip_suffix("e.p.") {
+            body = stripped.trim_end();
+        }
+        let body = body.trim_end_matches(|c| "+#!?".contains(c));
+        if body.is_empty() {
+            return Err(malformed());
+        }
+
+        let candidates: Vec<Action> = match body {
+            "O-O" | "0-0" | "O-O-O" | "0-0-0" => {
+                let queenside = body.len() == 5;
+                legal_moves(state)
+                    .into_iter()
+                    .filter(|a| match *a {
+                        Action::Castling { queenside: q, .. } => q == queenside,
+                        _ => false,
+                    })
+                    .collect()
+            }
+            _ => {
+                let mut chars: Vec<char> = body.chars().collect();
+
+                let piece_type = match letter_to_piece_type(chars[0]) {
+                    Some(pt) => {
+                        chars.remove(0);
+                        Some(pt)
+                    }
+                    None => None,
+                };
+
+                let promotion = match chars.last().copied().and_then(letter_to_piece_type) {
+                    Some(pt) if piece_type.is_none() => {
+                        chars.pop();
+                        if chars.last() == Some(&'=') {
+                            chars.pop();
+                        }
+                        match pt {
+                            PT::King { .. } => return Err(malformed()),
+                            _ => Some(pt),
+                        }
+                    }
+                    _ => None,
+                };
+
+                if chars.len() < 2 {
+                    return Err(malformed());
+                }
+                let destination: String = chars.split_off(chars.len() - 2).into_iter().collect();
+                let (e_y, e_x) = parse_square(&destination).ok_or_else(malformed)?;
+
+                if let Some('x') | Some(':') = chars.last() {
+                    chars.pop();
+                }
+
+                let (mut from_y, mut from_x) = (None, None);
+                for c in chars {
+                    match c {
+                        'a'..='h' if from_x.is_none() => from_x = Some((c as u8 - b'a') as usize),
+                        '1'..='8' if from_y.is_none() => {
+                            from_y = Some(8 - (c as u8 - b'0') as usize)
+                        }
+                        _ => return Err(malformed()),
+                    }
+                }
+
+                let moving_type = piece_type.unwrap_or(PT::Pawn { last_jump: None });
+                legal_moves(state)
+                    .into_iter()
+                    .filter(|a| {
+                        let (s_y, s_x) = a.get_main_coords();
+                        let promoted_to = match *a {
+                            Action::Promotion { to_piece, .. } => Some(to_piece),
+                            _ => None,
+                        };
+
+                        a.get_end_coords() == (e_y, e_x)
+                            && from_y.is_none_or(|y| y == s_y)
+                            && from_x.is_none_or(|x| x == s_x)
+                            && matches!(
+                                a.get_main_piece(state),
+                                Some(Piece { t, .. }) if same_piece_type(t, moving_type)
+                            )
+                            && match (promoted_to, promotion) {
+                                (None, None) => true,
+                                (Some(a), Some(b)) => same_piece_type(a, b),
+                                _ => false,
+                            }
+                    })
+                    .collect()
+            }
+        };
+
+        match candidates.len() {
+            0 => Err(SanError::Illegal(san.to_string())),
+            1 => Ok(candidates[0]),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+// Writes a sequence of actions played from state in SAN, separated by spaces.
+pub fn line_to_san(state: &State, line: &[Action]) -> String {
+    let mut state = state.clone();
+    let mut sans = Vec::with_capacity(line.len());
+    for action in line {
+        sans.push(action.to_san(&state));
+        state = result(&state, action);
+    }
+
+    sans.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that the move given in UCI notation is written as expected, and read back.
+    fn check(fen: &str, uci: &str, expected: &str) {
+        let state = State::from_fen(fen).unwrap();
+        let action = Action::from_uci(&state, uci).unwrap();
+        assert_eq!(action.to_san(&state), expected, "{} in {}", uci, fen);
+        assert_eq!(
+            Action::from_san(&state, expected),
+            Ok(action),
+            "{}",
+            expected
+        );
+    }
+
+    #[test]
+    fn disambiguation() {
+        let knights = "4k3/8/8/6N1/8/8/8/1N2KNN1 w - - 0 1";
+        check(knights, "b1d2", "Nbd2");
+        check(knights, "f1d2", "Nfd2");
+        check(knights, "g1e2", "Ne2");
+        check(knights, "g1f3", "N1f3");
+        check(knights, "g5f3", "N5f3");
+
+        let queens = "6k1/8/8/Q7/8/8/7K/Q3Q3 w - - 0 1";
+        check(queens, "a1e5", "Qa1e5");
+        check(queens, "a5e5", "Q5e5");
+        check(queens, "e1e5", "Qee5");
+        check(queens, "a1b2", "Qb2");
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        check(fen, "e1g1", "O-O");
+        check(fen, "e1c1", "O-O-O");
+        let state = State::from_fen(fen).unwrap();
+        assert_eq!(
+            Action::from_san(&state, "0-0-0"),
+            Action::from_san(&state, "O-O-O")
+        );
+    }
+
+    #[test]
+    fn promotions() {
+        check("7k/P7/8/8/8/8/8/K7 w - - 0 1", "a7a8q", "a8=Q+");
+        check("1n5k/P7/8/8/8/8/8/K7 w - - 0 1", "a7b8n", "axb8=N");
+        check("1n5k/P7/8/8/8/8/8/K7 w - - 0 1", "a7a8q", "a8=Q");
+        check("8/8/8/8/8/8/p6k/2K5 b - - 0 1", "a2a1r", "a1=R+");
+
+        let state = State::from_fen("1n5k/P7/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        assert_eq!(
+            Action::from_san(&state, "a8Q"),
+            Action::from_san(&state, "a8=Q")
+        );
+    }
+
+    #[test]
+    fn checks_and_mates() {
+        check("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8+");
+        check("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8", "Ra8#");
+        check(
+            "rnbqkbnr/ppppp2p/5p2/6p1/4P3/8/PPPP1PPP/RNBQKBNR w KQkq g6 0 3",
+            "d1h5",
+            "Qh5#",
+        );
+    }
+
+    #[test]
+    fn en_passant() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2";
+        check(fen, "e5d6", "exd6");
+        let state = State::from_fen(fen).unwrap();
+        assert_eq!(
+            Action::from_san(&state, "exd6 e.p."),
+            Action::from_san(&state, "exd6")
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let knights = State::from_fen("4k3/8/8/6N1/8/8/8/1N2KNN1 w - - 0 1").unwrap();
+        let queens = State::from_fen("6k1/8/8/Q7/8/8/7K/Q3Q3 w - - 0 1").unwrap();
+        let cases = [
+            (&knights, "Nd2", SanError::Ambiguous("Nd2".to_string())),
+            (&knights, "Nf3", SanError::Ambiguous("Nf3".to_string())),
+            (&queens, "Qe5", SanError::Ambiguous("Qe5".to_string())),
+            (&queens, "Qae5", SanError::Ambiguous("Qae5".to_string())),
+            (&knights, "Nd3", SanError::Illegal("Nd3".to_string())),
+            (&knights, "Ke3", SanError::Illegal("Ke3".to_string())),
+            (&knights, "O-O", SanError::Illegal("O-O".to_string())),
+            (&knights, "e4", SanError::Illegal("e4".to_string())),
+            (&knights, "", SanError::Malformed("".to_string())),
+            (&knights, "Nz9", SanError::Malformed("Nz9".to_string())),
+            (
+                &knights,
+                "Nb1bd2",
+                SanError::Malformed("Nb1bd2".to_string()),
+            ),
+            (&knights, "e8=K", SanError::Malformed("e8=K".to_string())),
+        ];
+        for (state, san, error) in cases.iter() {
+            assert_eq!(Action::from_san(state, san), Err(error.clone()), "{}", san);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::action::Action;
+use crate::bitboard::{bit, color_index, piece_index, square, KING, PAWN};
+use crate::state::State;
+
+// Piece values in centipawns, indexed like the bitboards. They follow piece_worth, but the king
+// is only worth enough that no exchange ever gives it up.
+const SEE_VALUES: [i32; 6] = [100, 320, 333, 510, 880, 20000];
+
+// Static exchange evaluation: the material the side to move wins with action, in centipawns,
+// assuming that both sides keep recapturing on the end square with their least valuable piece for
+// as long as that pays off. Pins and checks are ignored. Actions that capture nothing are scored
+// as if the moved piece could be taken.
+pub fn see(state: &State, action: &Action) -> i32 {
+    let (s_y, s_x, e_y, e_x) = match *action {
+        Action::Jump { s_y, s_x, e_y, e_x }
+        | Action::Capture { s_y, s_x, e_y, e_x }
+        | Action::Promotion {
+            s_y, s_x, e_y, e_x, ..
+        }
+        | Action::Enpassant { s_y, s_x, e_y, e_x } => (s_y, s_x, e_y, e_x),
+        Action::Castling { .. } | Action::Tie => return 0,
+    };
+    let target = square(e_y, e_x);
+    let bitboards = &state.bitboards;
+    let mut occupied = bitboards.occupied() & !bit(square(s_y, s_x));
+
+    // gains[d] is what the side making the d-th capture has won if the exchange stops right after
+    // it, the moved piece having been taken if d is odd.
+    let mut gains = [0; 32];
+    let mut attacker_value = match *action {
+        Action::Promotion { to_piece, .. } => {
+            gains[0] = SEE_VALUES[piece_index(to_piece)] - SEE_VALUES[PAWN];
+            SEE_VALUES[piece_index(to_piece)]
+        }
+        _ => action
+            .get_main_piece(state)
+            .map_or(0, |piece| SEE_VALUES[piece_index(piece.t)]),
+    };
+    match *action {
+        Action::Enpassant { s_y, .. } => {
+            gains[0] += SEE_VALUES[PAWN];
+            occupied &= !bit(square(s_y, e_x));
+        }
+        _ => {
+            if let Some(piece) = state.board[e_y][e_x] {
+                gains[0] += SEE_VALUES[piece_index(piece.t)];
+            }
+        }
+    }
+
+    let mut attackers = bitboards.attackers_to(target, occupied);
+    let mut side = state.turn.opponent();
+    let mut d = 0;
+    while d + 1 < gains.len() {
+        d += 1;
+        gains[d] = attacker_value - gains[d - 1];
+        // Neither side can do better by continuing.
+        if i32::max(-gains[d - 1], gains[d]) < 0 {
+            break;
+        }
+
+        let own_attackers = attackers & bitboards.colors[color_index(side)];
+        let next = (PAWN..=KING).find_map(|t| {
+            let candidates = own_attackers & bitboards.of(side, t);
+            (candidates != 0).then_some((t, candidates & candidates.wrapping_neg()))
+        });
+        let (t, from) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        // A king cannot capture onto a square the other side still attacks.
+        if t == KING && attackers & !own_attackers != 0 {
+            break;
+        }
+
+        // Sliders behind the piece that just captured now see the target square.
+        occupied &= !from;
+        attackers = bitboards.attackers_to(target, occupied);
+        attacker_value = SEE_VALUES[t];
+        side = side.opponent();
+    }
+
+    // Going back from the last capture, each side chooses between capturing and stopping.
+    while d > 1 {
+        d -= 1;
+        gains[d - 1] = -i32::max(-gains[d - 1], gains[d]);
+    }
+    gains[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see_uci(fen: &str, uci: &str) -> i32 {
+        let state = State::from_fen(fen).unwrap();
+        see(&state, &Action::from_uci(&state, uci).unwrap())
+    }
+
+    #[test]
+    fn undefended_pawn() {
+        assert_eq!(
+            see_uci("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+            100
+        );
+    }
+
+    #[test]
+    fn losing_exchange_with_x_rays() {
+        // Nxe5 Nxe5 Rxe5 Bxe5 Qxe5 Qxe5: white should stop after the knight is taken.
+        let fen = "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1";
+        assert_eq!(see_uci(fen, "d3e5"), 100 - SEE_VALUES[1]);
+    }
+
+    #[test]
+    fn quiet_move_to_attacked_square() {
+        assert_eq!(
+            see_uci("4k3/8/8/8/4p3/8/8/4K1N1 w - - 0 1", "g1f3"),
+            -SEE_VALUES[1]
+        );
+        assert_eq!(see_uci("4k3/8/8/8/4p3/8/8/4K1N1 w - - 0 1", "g1h3"), 0);
+    }
+}
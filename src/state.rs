@@ -1,5 +1,9 @@
 use std::collections::HashMap;
 
+use crate::bitboard::{square, Bitboards};
+use crate::value::Termination;
+use crate::zobrist::{full_key, piece_key};
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum PC {
     Black,
@@ -89,70 +93,86 @@ impl Piece {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct State {
     pub board: [[Option<Piece>; 8]; 8],
+    // Mirrors board; only modify squares through set_square so the two stay in sync.
+    pub bitboards: Bitboards,
+    // Zobrist hash of the position, updated incrementally by set_square and inplace_result.
+    pub hash_key: u64,
     pub hash_to_occurences: HashMap<u64, i32>,
     pub turn: PC,
     pub moves: usize,
-    pub drawn: bool,
+    // Plies since the last capture or pawn move, for the fifty and seventy-five move rules.
+    pub halfmove_clock: usize,
+    // Set, with the reason, once the game is drawn by a claim or by fivefold repetition.
+    pub drawn: Option<Termination>,
 }
 
+// Either side may claim a draw once the halfmove clock reaches 100 plies, i.e. fifty moves each.
+pub const FIFTY_MOVE_RULE_PLIES: usize = 100;
+// At 150 plies the game is drawn without a claim.
+pub const SEVENTY_FIVE_MOVE_RULE_PLIES: usize = 150;
+
 impl State {
+    pub fn set_square(&mut self, y: usize, x: usize, piece: Option<Piece>) {
+        if let Some(old_piece) = &self.board[y][x] {
+            self.bitboards.clear(square(y, x), old_piece);
+            self.hash_key ^= piece_key(old_piece, square(y, x));
+        }
+        if let Some(new_piece) = &piece {
+            self.bitboards.set(square(y, x), new_piece);
+            self.hash_key ^= piece_key(new_piece, square(y, x));
+        }
+        self.board[y][x] = piece;
+    }
     pub fn hash(&self) -> u64 {
-        const B: u64 = 1156366624;
-        const MOD: u64 = 999999999999989;
-        const LOOKUP: [[u64; 8]; 8] = [
-            [37, 1369, 50653, 1874161, 69343957, 2565726409, 94931877133, 3512479453921],
-            [129961739795077, 808584372417893, 917621779462360, 952005840107683, 224216083984656, 295995107432360, 951818974997430, 217302074905295],
-            [40176771496003, 486540545352122, 2000178028712, 74006587062344, 738243721306750, 315017688350047, 655654468951860, 259215351219084],
-            [590967995106207, 865815818929890, 35185300406282, 301856115032445, 168676256200586, 241021479421748, 917794738604764, 958405328376631],
-            [460997149935732, 56894547622271, 105098262024049, 888635694889846, 879520710924654, 542266304212550, 63853255864570, 362570466989112],
-            [415107278597287, 358969308099784, 281864399692151, 428982788609697, 872363178558954, 277437606681650, 265191447221160, 812083547183019],
-            [47091245772033, 742376093565232, 467915461913881, 312872090813784, 576267360110129, 321892324075004, 910015990775269, 670591658685316],
-            [811891371356956, 39980740207702, 479287387684985, 733633344344632, 144433740751681, 344048407812252, 729791089053456, 2270294978169],
-        ];
-
-        let mut v = B;
-        v += match self.turn {
-            PC::Black => 17,
-            PC::White => 18,
-        };
-        v %= MOD;
-
-        for (i, board_row) in self.board.iter().enumerate() {
-            for (j, c) in board_row.iter().enumerate() {
-                let piece_hash = match c {
-                    None => 1,
-                    Some(p) => {
-                        let color_hash = match p.c {
-                            PC::Black => 0,
-                            PC::White => 1,
-                        };
-
-                        let type_hash = match p.t {
-                            PT::Pawn { last_jump }
-                                if self.moves > 0 && last_jump == Some(self.moves - 1) =>
-                            {
-                                0
-                            }
-                            PT::Pawn { .. } => 1,
-                            PT::Knight => 2,
-                            PT::Bishop => 3,
-                            PT::Rook { has_moved: false } => 4,
-                            PT::Rook { has_moved: true } => 5,
-                            PT::Queen => 6,
-                            PT::King { has_moved: false } => 7,
-                            PT::King { has_moved: true } => 8,
-                        };
-
-                        2 + 2 * type_hash + color_hash
-                    }
-                };
-
-                v += LOOKUP[i][j] * piece_hash;
-                v %= MOD;
-            }
+        self.hash_key
+    }
+    // Castling rights in FEN order: white kingside, white queenside, black kingside, black
+    // queenside. A right exists while the king and the rook have not moved.
+    pub fn castling_rights(&self) -> [bool; 4] {
+        let mut rights = [false; 4];
+        for (i, (c, y, rook_x)) in [
+            (PC::White, 7, 7),
+            (PC::White, 7, 0),
+            (PC::Black, 0, 7),
+            (PC::Black, 0, 0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let unmoved_king = matches!(
+                self.board[*y][4],
+                Some(Piece {
+                    c: king_color,
+                    t: PT::King { has_moved: false },
+                }) if king_color == *c
+            );
+            let unmoved_rook = matches!(
+                self.board[*y][*rook_x],
+                Some(Piece {
+                    c: rook_color,
+                    t: PT::Rook { has_moved: false },
+                }) if rook_color == *c
+            );
+            rights[i] = unmoved_king && unmoved_rook;
         }
-
-        v
+        rights
+    }
+    // The file of the pawn that jumped two squares on the previous move, if any.
+    pub fn en_passant_file(&self) -> Option<usize> {
+        if self.moves == 0 {
+            return None;
+        }
+        let y = match self.turn {
+            PC::White => 3,
+            PC::Black => 4,
+        };
+        (0..8).find(|&x| match self.board[y][x] {
+            Some(Piece {
+                c,
+                t: PT::Pawn { last_jump },
+            }) => c != self.turn && last_jump == Some(self.moves - 1),
+            _ => false,
+        })
     }
 
     pub fn new() -> Self {
@@ -230,11 +250,15 @@ impl State {
 
         let mut state = State {
             board,
+            bitboards: Bitboards::from_board(&board),
+            hash_key: 0,
             hash_to_occurences: HashMap::new(),
             turn: PC::White,
             moves: 0,
-            drawn: false,
+            halfmove_clock: 0,
+            drawn: None,
         };
+        state.hash_key = full_key(&state);
 
         *state.hash_to_occurences.entry(state.hash()).or_insert(0) += 1;
 
@@ -0,0 +1,1290 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::action::Action;
+use crate::actions::{actions, any_actions, is_king_attacked};
+use crate::bitboard::{color_index, piece_index, KING};
+use crate::result::{inplace_result, inplace_revert};
+use crate::state::{Piece, State, PC, PT};
+use crate::value::{value, GameResult};
+
+// Syzygy tables are indexed with the squares numbered from a1 upwards, as in most engines, while
+// the board starts at a8. Pieces are numbered 1 to 6 from pawn to king, plus 8 for black.
+//
+// The format and the probing code follow the reference implementation that comes with the tables:
+// positions are mapped to an index, and the value at that index is decompressed from blocks of
+// Huffman codes whose symbols each stand for a pair of other symbols.
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// Flags of a table, all of which but the last concern DTZ tables only.
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// Leaves of the pair tree have no right-hand symbol.
+const NO_SYMBOL: u16 = 0xfff;
+const MAX_PIECES: usize = 7;
+
+// The outcome of a position with the side to move under the fifty-move rule. A cursed win is a win
+// that takes too long to convert and so is a draw, and a blessed loss the other side of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Wdl> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32)).unwrap()
+    }
+}
+
+impl fmt::Display for Wdl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed win",
+            Wdl::Win => "win",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// DTZ tables do not store a value for positions whose best move resets the fifty-move counter,
+// but the distance is then known from the outcome alone.
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+fn sq_file(sq: usize) -> usize {
+    sq & 7
+}
+
+fn sq_rank(sq: usize) -> usize {
+    sq >> 3
+}
+
+// Distance of sq above the a1-h8 diagonal, negative below it.
+fn off_a1h8(sq: usize) -> i32 {
+    sq_rank(sq) as i32 - sq_file(sq) as i32
+}
+
+// Lookup tables for turning the squares of a position into an index.
+struct Indices {
+    // a2-h7 to 0..47, highest for the pawn that leads: nearest to the edge, then lowest.
+    map_pawns: [usize; 64],
+    // The squares below the a1-h8 diagonal to 0..27.
+    map_b1h1h7: [usize; 64],
+    // The a1-d1-d4 triangle to 0..9, the diagonal last.
+    map_a1d1d4: [usize; 64],
+    // The 462 placements of two kings with the first in the a1-d1-d4 triangle.
+    map_kk: [[usize; 64]; 10],
+    // binomial[k][n] ways to choose k of n squares.
+    binomial: [[u64; 64]; MAX_PIECES],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+impl Indices {
+    fn new() -> Self {
+        let mut indices = Indices {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                indices.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = vec![];
+        code = 0;
+        for sq in 0..28 {
+            if off_a1h8(sq) < 0 && sq_file(sq) <= 3 {
+                indices.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && sq_file(sq) <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            indices.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // With the first king on the diagonal, the second may not be above it.
+        let mut both_on_diagonal = vec![];
+        code = 0;
+        let map_a1d1d4 = indices.map_a1d1d4;
+        for idx in 0..10 {
+            // Squares outside the triangle map to 0 as well, b1 is the one that belongs there.
+            for s1 in (0..28).filter(|s1| map_a1d1d4[*s1] == idx && (idx > 0 || *s1 == 1)) {
+                for s2 in 0..64 {
+                    let adjacent = (sq_file(s1) as i32 - sq_file(s2) as i32).abs() <= 1
+                        && (sq_rank(s1) as i32 - sq_rank(s2) as i32).abs() <= 1;
+                    if adjacent || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        indices.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            indices.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        indices.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                indices.binomial[k][n] = if k > 0 {
+                    indices.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n { indices.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available_squares = 47;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        indices.map_pawns[sq] = available_squares;
+                        indices.map_pawns[sq ^ 7] = available_squares - 1;
+                        available_squares = available_squares.saturating_sub(2);
+                    }
+                    indices.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += indices.binomial[lead_pawns - 1][indices.map_pawns[sq]];
+                }
+                indices.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        indices
+    }
+}
+
+fn u16_le(bytes: &[u8], i: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(i..i + 2)?.try_into().ok()?))
+}
+
+fn u32_le(bytes: &[u8], i: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(i..i + 4)?.try_into().ok()?))
+}
+
+// Reads n big-endian bytes, counting those past the end of the file as zero.
+fn be_padded(bytes: &[u8], i: usize, n: usize) -> u64 {
+    (0..n).fold(0, |acc, k| {
+        acc << 8 | bytes.get(i + k).copied().unwrap_or(0) as u64
+    })
+}
+
+// How the values of one table, or of one side or leading pawn file of it, are stored. Offsets
+// point into the bytes of the table file.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    // Pieces are encoded in groups of the same piece, except for the leading group.
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    // There is an entry of the sparse index about every span values.
+    span: u64,
+    num_blocks: usize,
+    max_sym_len: u32,
+    min_sym_len: u32,
+    lowest_sym: usize,
+    // base64[l - min_sym_len] is the lowest symbol of length l, padded to 64 bits.
+    base64: Vec<u64>,
+    // The number of values, minus one, a symbol expands to.
+    symlen: Vec<u8>,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    // Where the DTZ values of wins, losses, cursed wins and blessed losses are remapped.
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    fn left(&self, bytes: &[u8], sym: u16) -> Option<u16> {
+        let entry = bytes.get(self.btree + 3 * sym as usize..self.btree + 3 * sym as usize + 3)?;
+        Some(((entry[1] as u16 & 0xf) << 8) | entry[0] as u16)
+    }
+
+    fn right(&self, bytes: &[u8], sym: u16) -> Option<u16> {
+        let entry = bytes.get(self.btree + 3 * sym as usize..self.btree + 3 * sym as usize + 3)?;
+        Some(((entry[2] as u16) << 4) | (entry[1] as u16 >> 4))
+    }
+
+    fn block_length(&self, bytes: &[u8], block: usize) -> Option<i64> {
+        if block >= self.block_length_size {
+            return None;
+        }
+        Some(u16_le(bytes, self.block_length + 2 * block)? as i64)
+    }
+
+    fn symlen(&self, sym: u16) -> Option<i64> {
+        Some(*self.symlen.get(sym as usize)? as i64)
+    }
+
+    fn set_symlen(&mut self, bytes: &[u8], sym: u16, visited: &mut [bool]) -> Option<u8> {
+        visited[sym as usize] = true;
+        let right = self.right(bytes, sym)?;
+        if right == NO_SYMBOL {
+            return Some(0);
+        }
+        let left = self.left(bytes, sym)?;
+
+        for child in [left, right] {
+            if !*visited.get(child as usize)? {
+                let len = self.set_symlen(bytes, child, visited)?;
+                self.symlen[child as usize] = len;
+            }
+        }
+        Some(
+            self.symlen[left as usize]
+                .wrapping_add(self.symlen[right as usize])
+                .wrapping_add(1),
+        )
+    }
+
+    // Reads the block sizes and the Huffman code, returning the offset of what follows.
+    fn set_sizes(&mut self, bytes: &[u8], mut offset: usize) -> Option<usize> {
+        self.flags = *bytes.get(offset)?;
+        offset += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // The single value is kept in min_sym_len.
+            self.min_sym_len = *bytes.get(offset)? as u32;
+            return Some(offset + 1);
+        }
+
+        let groups = self.group_len.iter().position(|len| *len == 0)?;
+        let tb_size = self.group_idx[groups];
+
+        self.block_size = 1usize.checked_shl(*bytes.get(offset)? as u32)?;
+        self.span = 1u64.checked_shl(*bytes.get(offset + 1)? as u32)?;
+        self.sparse_index_size = tb_size.div_ceil(self.span) as usize;
+        let padding = *bytes.get(offset + 2)? as usize;
+        self.num_blocks = u32_le(bytes, offset + 3)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        self.max_sym_len = *bytes.get(offset + 7)? as u32;
+        self.min_sym_len = *bytes.get(offset + 8)? as u32;
+        offset += 9;
+        self.lowest_sym = offset;
+        if self.max_sym_len < self.min_sym_len || self.max_sym_len > 32 {
+            return None;
+        }
+
+        // The canonical code gives longer symbols lower values, so base64 decreases.
+        let lengths = (self.max_sym_len - self.min_sym_len + 1) as usize;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = u16_le(bytes, self.lowest_sym + 2 * i)? as u64;
+            let next_lowest = u16_le(bytes, self.lowest_sym + 2 * (i + 1))? as u64;
+            self.base64[i] = self.base64[i + 1]
+                .wrapping_add(lowest)
+                .wrapping_sub(next_lowest)
+                / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base
+                .checked_shl(64 - i as u32 - self.min_sym_len)
+                .unwrap_or(0);
+        }
+        offset += 2 * lengths;
+
+        let symbols = u16_le(bytes, offset)? as usize;
+        offset += 2;
+        self.btree = offset;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                let len = self.set_symlen(bytes, sym as u16, &mut visited)?;
+                self.symlen[sym] = len;
+            }
+        }
+
+        Some(offset + 3 * symbols + (symbols & 1))
+    }
+
+    // The value stored at idx.
+    fn decompress(&self, bytes: &[u8], idx: u64) -> Option<i32> {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as i32);
+        }
+
+        // Every entry of the sparse index gives the block and the offset in it of the value at
+        // k * span + span / 2, from which the block of idx is found by walking the block lengths.
+        let k = (idx / self.span) as usize;
+        if k >= self.sparse_index_size {
+            return None;
+        }
+        let mut block = u32_le(bytes, self.sparse_index + 6 * k)? as usize;
+        let mut offset = u16_le(bytes, self.sparse_index + 6 * k + 4)? as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += self.block_length(bytes, block)? + 1;
+        }
+        while offset > self.block_length(bytes, block)? {
+            offset -= self.block_length(bytes, block)? + 1;
+            block += 1;
+        }
+
+        // Find the symbol that covers offset in the block.
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf64 = be_padded(bytes, ptr, 8);
+        ptr += 8;
+        let mut buf64_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < *self.base64.get(len)? {
+                len += 1;
+            }
+            sym = (buf64 - self.base64[len])
+                .checked_shr(64 - len as u32 - self.min_sym_len)
+                .unwrap_or(0) as u16;
+            sym = sym.wrapping_add(u16_le(bytes, self.lowest_sym + 2 * len)?);
+
+            if offset < self.symlen(sym)? + 1 {
+                break;
+            }
+            offset -= self.symlen(sym)? + 1;
+            let len = len as u32 + self.min_sym_len;
+            buf64 = buf64.checked_shl(len).unwrap_or(0);
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= be_padded(bytes, ptr, 4) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // Then expand it down to the single value at offset.
+        while self.symlen(sym)? != 0 {
+            let left = self.left(bytes, sym)?;
+            if offset < self.symlen(left)? + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen(left)? + 1;
+                sym = self.right(bytes, sym)?;
+            }
+        }
+
+        Some(self.left(bytes, sym)? as i32)
+    }
+}
+
+// A table file once read, with pairs[side * 4 + file] for every side to move it stores and, with
+// pawns, every file of the leading pawn.
+struct TableData {
+    bytes: Vec<u8>,
+    pairs: Vec<PairsData>,
+    sides: usize,
+    dtz_map: usize,
+}
+
+// The material of a table, named with the stronger side first as in "KRvK". Its first side is
+// white as far as the table is concerned.
+struct Table {
+    wdl_path: PathBuf,
+    dtz_path: PathBuf,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // Pawns of the leading side, which is the one with fewer pawns if both have some, and of the
+    // other side.
+    pawn_count: [usize; 2],
+    // Both sides have the same material.
+    symmetric: bool,
+    // Tables are only read when first needed.
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>,
+}
+
+// Piece counts of one side, indexed like the bitboards, in the notation of table names.
+fn side_name(counts: &[usize; 6]) -> String {
+    let mut name = String::new();
+    for t in (0..6).rev() {
+        for _ in 0..counts[t] {
+            name.push(b"PNBRQK"[t] as char);
+        }
+    }
+    name
+}
+
+fn parse_side(side: &str) -> Option<[usize; 6]> {
+    let mut counts = [0; 6];
+    for c in side.chars() {
+        counts[b"PNBRQK".iter().position(|p| *p as char == c)?] += 1;
+    }
+    if counts[KING] == 1 && side.starts_with('K') {
+        Some(counts)
+    } else {
+        None
+    }
+}
+
+impl Table {
+    fn new(dir: &Path, name: &str) -> Option<Table> {
+        let (white, black) = name.split_once('v')?;
+        let (white, black) = (parse_side(white)?, parse_side(black)?);
+        let piece_count = white.iter().chain(black.iter()).sum();
+        if piece_count > MAX_PIECES {
+            return None;
+        }
+
+        let (white_pawns, black_pawns) = (white[0], black[0]);
+        let lead_is_white = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(Table {
+            wdl_path: dir.join(format!("{}.rtbw", name)),
+            dtz_path: dir.join(format!("{}.rtbz", name)),
+            piece_count,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: white[..KING].contains(&1) || black[..KING].contains(&1),
+            pawn_count: if lead_is_white {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            symmetric: white == black,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn data(&self, kind: Kind, indices: &Indices) -> Option<&TableData> {
+        let (cell, path, magic) = match kind {
+            Kind::Wdl => (&self.wdl, &self.wdl_path, WDL_MAGIC),
+            Kind::Dtz => (&self.dtz, &self.dtz_path, DTZ_MAGIC),
+        };
+        cell.get_or_init(|| {
+            let bytes = fs::read(path).ok()?;
+            if bytes.get(..4) != Some(&magic[..]) {
+                log::warn!("Corrupted tablebase file {}", path.display());
+                return None;
+            }
+            let data = self.parse(bytes, kind, indices);
+            if data.is_none() {
+                log::warn!("Could not read tablebase file {}", path.display());
+            }
+            data
+        })
+        .as_ref()
+    }
+
+    // Groups the pieces the way the table encodes them and works out the factor of each group
+    // in the index, in the order given by the table.
+    fn set_groups(
+        &self,
+        d: &mut PairsData,
+        order: [u8; 2],
+        file: usize,
+        indices: &Indices,
+    ) -> Option<()> {
+        let mut n = 0;
+        let mut first_len = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares =
+            64usize.checked_sub(d.group_len[0] + if pp { d.group_len[1] } else { 0 })?;
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    *indices.lead_pawns_size.get(d.group_len[0])?.get(file)?
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                d.group_idx[1] = idx;
+                idx *=
+                    indices.binomial.get(d.group_len[1])?[48usize.checked_sub(d.group_len[0])?];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= indices.binomial.get(d.group_len[next])?[free_squares];
+                free_squares = free_squares.checked_sub(d.group_len[next])?;
+                next += 1;
+            }
+            k += 1;
+            if k > 16 {
+                return None;
+            }
+        }
+        d.group_idx[n] = idx;
+        Some(())
+    }
+
+    fn parse(&self, bytes: Vec<u8>, kind: Kind, indices: &Indices) -> Option<TableData> {
+        // The flags tell whether the table is split by side to move, which is only used for WDL
+        // tables, and whether it has pawns.
+        let flags = *bytes.get(4)?;
+        if (flags & 2 != 0) != self.has_pawns {
+            return None;
+        }
+
+        let sides = if kind == Kind::Wdl && !self.symmetric {
+            2
+        } else {
+            1
+        };
+        let files = if self.has_pawns { 4 } else { 1 };
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut pairs = vec![PairsData::default(); 2 * 4];
+        let mut offset = 5;
+
+        for file in 0..files {
+            let first = *bytes.get(offset)?;
+            let second = if pp { *bytes.get(offset + 1)? } else { 0xff };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            offset += 1 + pp as usize;
+
+            for k in 0..self.piece_count {
+                let piece = *bytes.get(offset)?;
+                for (side, order) in order.iter().enumerate().take(sides) {
+                    let d = &mut pairs[side * 4 + file];
+                    d.pieces[k] = if side == 0 { piece & 0xf } else { piece >> 4 };
+                    if k == self.piece_count - 1 {
+                        let mut d = d.clone();
+                        self.set_groups(&mut d, *order, file, indices)?;
+                        pairs[side * 4 + file] = d;
+                    }
+                }
+                offset += 1;
+            }
+        }
+        offset += offset & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                offset = pairs[side * 4 + file].set_sizes(&bytes, offset)?;
+            }
+        }
+
+        // DTZ tables can remap their values, so that the most common ones take the fewest bits.
+        let dtz_map = offset;
+        if kind == Kind::Dtz {
+            for d in pairs.iter_mut().take(files) {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & FLAG_WIDE != 0 {
+                    offset += offset & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (offset - dtz_map) / 2 + 1;
+                        offset += 2 * u16_le(&bytes, offset)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = offset - dtz_map + 1;
+                        offset += *bytes.get(offset)? as usize + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut pairs[side * 4 + file];
+                d.sparse_index = offset;
+                offset += 6 * d.sparse_index_size;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut pairs[side * 4 + file];
+                d.block_length = offset;
+                offset += 2 * d.block_length_size;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut pairs[side * 4 + file];
+                offset = (offset + 0x3f) & !0x3f;
+                d.data = offset;
+                offset += d.num_blocks * d.block_size;
+            }
+        }
+        if offset > bytes.len() {
+            return None;
+        }
+
+        Some(TableData {
+            bytes,
+            pairs,
+            sides,
+            dtz_map,
+        })
+    }
+}
+
+impl TableData {
+    fn pairs(&self, stm: usize, file: usize) -> &PairsData {
+        &self.pairs[(stm % self.sides) * 4 + file]
+    }
+
+    // Undoes the remapping of DTZ values and converts them to plies.
+    fn map_dtz(&self, file: usize, value: i32, wdl: Wdl) -> Option<i32> {
+        let d = self.pairs(0, file);
+        let mut value = value;
+        if d.flags & FLAG_MAPPED != 0 {
+            let idx = d.map_idx[match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            }] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                u16_le(&self.bytes, self.dtz_map + 2 * idx)? as i32
+            } else {
+                *self.bytes.get(self.dtz_map + idx)? as i32
+            };
+        }
+
+        let in_moves = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        if in_moves {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+}
+
+enum TableValue {
+    Value(i32),
+    // A DTZ table only stores one side to move, which is not the one of the position.
+    ChangeStm,
+}
+
+fn legal_actions(state: &State) -> Vec<Action> {
+    let mut found = actions(state);
+    found.retain(|a| *a != Action::Tie);
+    found
+}
+
+fn is_capture(state: &State, action: &Action) -> bool {
+    match *action {
+        Action::Capture { .. } | Action::Enpassant { .. } => true,
+        Action::Promotion { e_y, e_x, .. } => state.board[e_y][e_x].is_some(),
+        _ => false,
+    }
+}
+
+fn is_pawn_move(state: &State, action: &Action) -> bool {
+    matches!(
+        action.get_main_piece(state),
+        Some(Piece {
+            t: PT::Pawn { .. },
+            ..
+        })
+    )
+}
+
+fn is_checkmate(state: &State) -> bool {
+    is_king_attacked(state, state.turn) && !any_actions(state)
+}
+
+// Syzygy endgame tablebases found in one or more directories. A table is used once all of its
+// files are there: .rtbw for win/draw/loss and .rtbz for the distance to the next capture or pawn
+// move.
+pub struct Tablebase {
+    tables: HashMap<String, Table>,
+    max_pieces: usize,
+    indices: Indices,
+}
+
+impl Tablebase {
+    // Directories are separated like in the PATH environment variable. The tables themselves are
+    // read into memory when first probed.
+    pub fn open(paths: &str) -> io::Result<Tablebase> {
+        let mut tables = HashMap::new();
+        for dir in env::split_paths(paths) {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension() != Some(OsStr::new("rtbw")) {
+                    continue;
+                }
+                let name = match path.file_stem().and_then(OsStr::to_str) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                if let Some(table) = Table::new(&dir, &name) {
+                    tables.insert(name, table);
+                }
+            }
+        }
+
+        let max_pieces = tables.values().map(|t| t.piece_count).max().unwrap_or(0);
+        Ok(Tablebase {
+            tables,
+            max_pieces,
+            indices: Indices::new(),
+        })
+    }
+
+    // The number of pieces, kings included, of the largest table.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    // Tables know nothing of castling, and nothing of positions with more pieces than they have.
+    fn covers(&self, state: &State) -> bool {
+        state.bitboards.occupied().count_ones() as usize <= self.max_pieces
+            && !state.castling_rights().iter().any(|r| *r)
+    }
+
+    fn probe_table(&self, state: &State, kind: Kind, wdl: Wdl) -> Option<TableValue> {
+        let mut counts = [[0; 6]; 2];
+        let mut board_pieces = vec![];
+        for (y, row) in state.board.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                if let Some(piece) = piece {
+                    counts[color_index(piece.c)][piece_index(piece.t)] += 1;
+                    let code =
+                        piece_index(piece.t) as u8 + 1 + if piece.c == PC::Black { 8 } else { 0 };
+                    board_pieces.push(((7 - y) * 8 + x, code));
+                }
+            }
+        }
+        // Tables go through the squares in this order.
+        board_pieces.sort_unstable();
+        if board_pieces.len() == 2 {
+            return Some(TableValue::Value(0));
+        }
+
+        let (white, black) = (side_name(&counts[0]), side_name(&counts[1]));
+        let (table, black_stronger) = match self.tables.get(&format!("{}v{}", white, black)) {
+            Some(table) => (table, false),
+            None => (self.tables.get(&format!("{}v{}", black, white))?, true),
+        };
+        let data = table.data(kind, &self.indices)?;
+        let indices = &self.indices;
+
+        // Tables are for white as the stronger side, and symmetric ones only for white to move,
+        // so other positions are looked up with the colors swapped and the board flipped.
+        let black_to_move = state.turn == PC::Black;
+        let flip = black_stronger || (table.symmetric && black_to_move);
+        let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+        let stm = (flip ^ black_to_move) as usize;
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut lead_color = None;
+        let mut file = 0;
+        if table.has_pawns {
+            // The pawns of the leading side come first, with the one nearest to the edge, then
+            // lowest, in front.
+            let lead_code = data.pairs(0, 0).pieces[0] ^ flip_color;
+            lead_color = Some(lead_code);
+            for (sq, code) in &board_pieces {
+                if *code == lead_code {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_pawns = size;
+            let lead = (0..lead_pawns)
+                .rev()
+                .max_by_key(|i| indices.map_pawns[squares[*i]])?;
+            squares.swap(0, lead);
+            file = sq_file(squares[0]).min(7 - sq_file(squares[0]));
+        }
+
+        if kind == Kind::Dtz
+            && (data.pairs(0, file).flags & FLAG_STM) as usize != stm
+            && (!table.symmetric || table.has_pawns)
+        {
+            return Some(TableValue::ChangeStm);
+        }
+
+        for (sq, code) in &board_pieces {
+            if Some(*code) == lead_color {
+                continue;
+            }
+            *squares.get_mut(size)? = sq ^ flip_squares;
+            pieces[size] = code ^ flip_color;
+            size += 1;
+        }
+        if size != table.piece_count {
+            return None;
+        }
+
+        // Put the pieces in the order of the table.
+        let d = data.pairs(stm, file);
+        for i in lead_pawns..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|j| d.pieces[i] == pieces[*j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // The leading piece goes to files a-d.
+        if sq_file(squares[0]) > 3 {
+            for sq in squares.iter_mut().take(size) {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if table.has_pawns {
+            idx = indices.lead_pawn_idx.get(lead_pawns)?[squares[0]];
+            squares[1..lead_pawns].sort_by_key(|sq| indices.map_pawns[*sq]);
+            for (i, sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += indices.binomial[i][indices.map_pawns[*sq]];
+            }
+        } else {
+            // Without pawns the leading piece also goes to ranks 1-4 and below the diagonal.
+            if sq_rank(squares[0]) > 3 {
+                for sq in squares.iter_mut().take(size) {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares.iter_mut().take(size).skip(i) {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if table.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as usize;
+                let adjust2 =
+                    (squares[2] > squares[0]) as usize + (squares[2] > squares[1]) as usize;
+                idx = if off_a1h8(squares[0]) != 0 {
+                    ((indices.map_a1d1d4[squares[0]] * 63 + squares[1] - adjust1) * 62 + squares[2]
+                        - adjust2) as u64
+                } else if off_a1h8(squares[1]) != 0 {
+                    ((6 * 63 + sq_rank(squares[0]) * 28 + indices.map_b1h1h7[squares[1]]) * 62
+                        + squares[2]
+                        - adjust2) as u64
+                } else if off_a1h8(squares[2]) != 0 {
+                    (6 * 63 * 62
+                        + 4 * 28 * 62
+                        + sq_rank(squares[0]) * 7 * 28
+                        + (sq_rank(squares[1]) - adjust1) * 28
+                        + indices.map_b1h1h7[squares[2]]) as u64
+                } else {
+                    (6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + sq_rank(squares[0]) * 7 * 6
+                        + (sq_rank(squares[1]) - adjust1) * 6
+                        + (sq_rank(squares[2]) - adjust2)) as u64
+                };
+            } else {
+                idx = indices.map_kk[indices.map_a1d1d4[squares[0]]][squares[1]] as u64;
+            }
+        }
+
+        // The other groups are encoded by their squares, skipping those taken by earlier groups.
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let group_end = group_start + d.group_len[next];
+            squares.get_mut(group_start..group_end)?.sort_unstable();
+            let mut n = 0;
+            for i in 0..d.group_len[next] {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|s| sq > **s).count();
+                let offset = if remaining_pawns { 8 } else { 0 };
+                n += indices.binomial.get(i + 1)?[sq.checked_sub(adjust + offset)?];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start = group_end;
+            next += 1;
+        }
+
+        let value = d.decompress(&data.bytes, idx)?;
+        match kind {
+            Kind::Wdl => Some(TableValue::Value(value - 2)),
+            Kind::Dtz => Some(TableValue::Value(data.map_dtz(file, value, wdl)?)),
+        }
+    }
+
+    fn probe_wdl_table(&self, state: &State) -> Option<Wdl> {
+        match self.probe_table(state, Kind::Wdl, Wdl::Draw)? {
+            TableValue::Value(value) => Wdl::from_value(value),
+            TableValue::ChangeStm => None,
+        }
+    }
+
+    // Tables may store anything for positions where a capture wins, or where a capture draws and
+    // the position is not won, since the best capture gives the result. So captures, and for DTZ
+    // pawn moves, are tried before the table. Also returns whether such a move is best.
+    fn search(&self, state: &mut State, check_zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = legal_actions(state);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for a in &moves {
+            if !(is_capture(state, a) || check_zeroing && is_pawn_move(state, a)) {
+                continue;
+            }
+            searched += 1;
+
+            let undo = inplace_result(state, a);
+            let response = self.search(state, false);
+            inplace_revert(state, undo);
+
+            let wdl = -response?.0;
+            if wdl > best {
+                best = wdl;
+                if wdl >= Wdl::Win {
+                    return Some((wdl, true));
+                }
+            }
+        }
+
+        // Positions where every move was tried, which include those with an en-passant capture,
+        // are not looked up since the table may be wrong about them.
+        let all_searched = searched > 0 && searched == moves.len();
+        let wdl = if all_searched {
+            best
+        } else {
+            self.probe_wdl_table(state)?
+        };
+        if best >= wdl {
+            Some((best, best > Wdl::Draw || all_searched))
+        } else {
+            Some((wdl, false))
+        }
+    }
+
+    // The outcome of the position with best play, assuming the fifty-move counter has just been
+    // reset. The state is changed during the probe but left as it was.
+    pub fn probe_wdl(&self, state: &mut State) -> Option<Wdl> {
+        if !self.covers(state) {
+            return None;
+        }
+        Some(self.search(state, false)?.0)
+    }
+
+    // The number of plies to the next capture or pawn move with best play, positive when the side
+    // to move wins and negative when it loses, or 0 for a draw. It can be one ply off when the
+    // table stores moves rather than plies, but never in a way that would change the outcome.
+    pub fn probe_dtz(&self, state: &mut State) -> Option<i32> {
+        if !self.covers(state) {
+            return None;
+        }
+        let (wdl, zeroing_is_best) = self.search(state, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_is_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        match self.probe_table(state, Kind::Dtz, wdl)? {
+            TableValue::Value(dtz) => {
+                let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum())
+            }
+            TableValue::ChangeStm => {
+                // Look one move ahead, where the table has the other side to move.
+                let mut min_dtz = i32::MAX;
+                for a in legal_actions(state) {
+                    let zeroing = is_capture(state, &a) || is_pawn_move(state, &a);
+                    let undo = inplace_result(state, &a);
+                    let dtz = if zeroing {
+                        self.search(state, false)
+                            .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+                    } else {
+                        self.probe_dtz(state).map(|dtz| -dtz)
+                    };
+                    let mates = is_checkmate(state);
+                    inplace_revert(state, undo);
+
+                    let mut dtz = dtz?;
+                    if dtz == 1 && mates {
+                        min_dtz = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                        min_dtz = dtz;
+                    }
+                }
+                Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+            }
+        }
+    }
+
+    // The move that makes the most progress in a won position, or that holds out longest in a
+    // lost one, with the outcome of the position. A mate is preferred to anything else. Drawn
+    // positions give None, since the tables cannot tell drawing moves apart.
+    pub fn best_move(&self, state: &mut State) -> Option<(Action, Wdl)> {
+        if !self.covers(state) {
+            return None;
+        }
+
+        // (dtz counted from this position, mates, action)
+        let mut ranked = vec![];
+        for a in legal_actions(state) {
+            let undo = inplace_result(state, &a);
+            let dtz = if state.halfmove_clock == 0 {
+                self.probe_wdl(state).map(|wdl| dtz_before_zeroing(-wdl))
+            } else if let GameResult::Tie(_) = value(state) {
+                Some(0)
+            } else {
+                self.probe_dtz(state).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = is_checkmate(state);
+            inplace_revert(state, undo);
+
+            let mut dtz = dtz?;
+            if mates {
+                dtz = 1;
+            }
+            ranked.push((dtz, mates, a));
+        }
+
+        let (dtz, _, action) = if ranked.iter().any(|(dtz, _, _)| *dtz > 0) {
+            ranked
+                .into_iter()
+                .filter(|(dtz, _, _)| *dtz > 0)
+                .min_by_key(|(dtz, mates, _)| (*dtz, !*mates))?
+        } else if ranked.iter().all(|(dtz, _, _)| *dtz < 0) {
+            ranked.into_iter().min_by_key(|(dtz, _, _)| *dtz)?
+        } else {
+            return None;
+        };
+
+        // The fifty-move rule turns a win or loss that takes too long into a draw.
+        let plies_left = 100 - state.halfmove_clock.min(100) as i32;
+        let wdl = match dtz {
+            dtz if dtz > 0 && dtz <= plies_left => Wdl::Win,
+            dtz if dtz > 0 => Wdl::CursedWin,
+            dtz if -dtz <= plies_left => Wdl::Loss,
+            _ => Wdl::BlessedLoss,
+        };
+        Some((action, wdl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Squares as numbered by the tables.
+    fn sq(name: &str) -> usize {
+        let bytes = name.as_bytes();
+        (bytes[1] - b'1') as usize * 8 + (bytes[0] - b'a') as usize
+    }
+
+    #[test]
+    fn king_placements() {
+        let indices = Indices::new();
+
+        let triangle = ["b1", "c1", "d1", "c2", "d2", "d3", "a1", "b2", "c3", "d4"];
+        for (code, name) in triangle.iter().enumerate() {
+            assert_eq!(indices.map_a1d1d4[sq(name)], code, "{}", name);
+        }
+        assert_eq!(indices.map_b1h1h7[sq("b1")], 0);
+        assert_eq!(indices.map_b1h1h7[sq("h7")], 27);
+
+        // Every one of the 462 codes is used by exactly one legal placement.
+        let mut used = [false; 462];
+        for (idx, first) in triangle.iter().enumerate() {
+            let s1 = sq(first);
+            for s2 in 0..64 {
+                let adjacent = (sq_file(s1) as i32 - sq_file(s2) as i32).abs() <= 1
+                    && (sq_rank(s1) as i32 - sq_rank(s2) as i32).abs() <= 1;
+                if adjacent || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                    continue;
+                }
+                let code = indices.map_kk[idx][s2];
+                assert!(!used[code], "code {} used twice", code);
+                used[code] = true;
+            }
+        }
+        assert!(used.iter().all(|u| *u));
+    }
+
+    #[test]
+    fn binomials() {
+        let indices = Indices::new();
+        for k in 0..MAX_PIECES {
+            for n in 0..64 {
+                let expected = if n < k as u64 {
+                    0
+                } else {
+                    (0..k as u64).fold(1, |c, i| c * (n - i) / (i + 1))
+                };
+                assert_eq!(indices.binomial[k][n as usize], expected, "{} of {}", k, n);
+            }
+        }
+    }
+
+    #[test]
+    fn pawn_placements() {
+        let indices = Indices::new();
+
+        assert_eq!(indices.map_pawns[sq("a2")], 47);
+        assert_eq!(indices.map_pawns[sq("h2")], 46);
+        assert_eq!(indices.map_pawns[sq("a7")], 37);
+        assert_eq!(indices.map_pawns[sq("b2")], 35);
+        assert_eq!(indices.map_pawns[sq("d7")], 1);
+        assert_eq!(indices.map_pawns[sq("e7")], 0);
+
+        assert_eq!(indices.lead_pawns_size[1], [6, 6, 6, 6]);
+        assert_eq!(indices.lead_pawns_size[2], [252, 180, 108, 36]);
+        assert_eq!(indices.lead_pawns_size[3][0], 5201);
+        assert_eq!(indices.lead_pawn_idx[2][sq("a2")], 0);
+        assert_eq!(indices.lead_pawn_idx[2][sq("a3")], 47);
+        assert_eq!(indices.lead_pawn_idx[2][sq("d7")], 35);
+    }
+
+    // Needs the tables for KQvK, KRvK and KPvK in the directories of SYZYGY_PATH, and is skipped
+    // without them.
+    #[test]
+    fn probe_three_pieces() {
+        let path = match env::var("SYZYGY_PATH") {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let tablebase = Tablebase::open(&path).unwrap();
+
+        let cases = [
+            ("7k/Q7/6K1/8/8/8/8/8 w - - 0 1", Wdl::Win, Some(1)),
+            ("4k3/8/8/8/8/8/8/4KQ2 b - - 0 1", Wdl::Loss, None),
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", Wdl::Win, None),
+            ("8/8/8/8/8/8/6Rk/4K3 b - - 0 1", Wdl::Draw, Some(0)),
+            ("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", Wdl::Win, Some(1)),
+            ("k7/8/8/8/8/8/P7/K7 w - - 0 1", Wdl::Draw, Some(0)),
+        ];
+        for (fen, wdl, dtz) in cases.iter() {
+            let mut state = State::from_fen(fen).unwrap();
+            assert_eq!(tablebase.probe_wdl(&mut state), Some(*wdl), "{}", fen);
+
+            let found_dtz = tablebase.probe_dtz(&mut state).unwrap();
+            assert_eq!(found_dtz.signum(), wdl.signum(), "{}", fen);
+            if let Some(dtz) = dtz {
+                assert_eq!(found_dtz, *dtz, "{}", fen);
+            }
+        }
+
+        // The only move that wins at once is a mate.
+        let mut state = State::from_fen("7k/Q7/6K1/8/8/8/8/8 w - - 0 1").unwrap();
+        let (action, wdl) = tablebase.best_move(&mut state).unwrap();
+        assert_eq!(wdl, Wdl::Win);
+        assert!(is_checkmate(&crate::result::result(&state, &action)));
+    }
+}
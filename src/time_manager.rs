@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+// Kept in reserve on every move for the time it takes to communicate with the GUI.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+// Assumed number of moves until the next time control when the clock does not say.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+// How much longer than planned a single move may take when an iteration runs long.
+const HARD_LIMIT_FACTOR: u32 = 3;
+// Every iteration of iterative deepening is assumed to take at least this many times as long as
+// the previous one.
+const ITERATION_GROWTH: u32 = 2;
+
+// How long the search for a single move may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeBudget {
+    // No new iteration is started when it would likely end after this.
+    pub soft: Duration,
+    // The search is abandoned once this has passed.
+    pub hard: Duration,
+}
+
+impl TimeBudget {
+    pub fn fixed(time: Duration) -> Self {
+        TimeBudget {
+            soft: time,
+            hard: time,
+        }
+    }
+
+    // Spreads the remaining time over the moves left until the next time control, spending most
+    // of the increment as well. The hard limit never takes more than half of what remains unless
+    // this is the last move before the time control.
+    pub fn from_clock(remaining: Duration, increment: Duration, moves_to_go: Option<u32>) -> Self {
+        let available = remaining.saturating_sub(MOVE_OVERHEAD);
+        let moves_to_go = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+        let soft = (available / moves_to_go + increment * 3 / 4).min(available);
+        let hard = (soft * HARD_LIMIT_FACTOR).min(available / 2).max(soft);
+
+        TimeBudget { soft, hard }
+    }
+
+    pub fn can_start_iteration(&self, elapsed: Duration, last_iteration: Duration) -> bool {
+        elapsed + last_iteration * ITERATION_GROWTH <= self.soft
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn budget(remaining: u64, increment: u64, moves_to_go: Option<u32>) -> (u64, u64) {
+        let budget = TimeBudget::from_clock(ms(remaining), ms(increment), moves_to_go);
+        (
+            budget.soft.as_millis() as u64,
+            budget.hard.as_millis() as u64,
+        )
+    }
+
+    #[test]
+    fn sudden_death() {
+        // 30 seconds after the overhead, spread over the default 30 moves.
+        assert_eq!(budget(30_050, 0, None), (1000, 3000));
+        // Most of the increment is spent as well.
+        assert_eq!(budget(30_050, 2000, None), (2500, 7500));
+        // Nothing is left once the overhead is taken off, increment or not.
+        assert_eq!(budget(30, 0, None), (0, 0));
+        assert_eq!(budget(30, 1000, None), (0, 0));
+    }
+
+    #[test]
+    fn moves_to_go() {
+        assert_eq!(budget(30_050, 0, Some(10)), (3000, 9000));
+        // The hard limit is capped at half of what remains.
+        assert_eq!(budget(30_050, 0, Some(4)), (7500, 15_000));
+        // Except on the last move before the time control.
+        assert_eq!(budget(30_050, 0, Some(1)), (30_000, 30_000));
+        assert_eq!(budget(30_050, 0, Some(0)), (30_000, 30_000));
+        assert_eq!(budget(30_050, 5000, Some(1)), (30_000, 30_000));
+    }
+
+    #[test]
+    fn iterations() {
+        let budget = TimeBudget::fixed(ms(1000));
+        assert_eq!(budget.hard, ms(1000));
+        assert!(budget.can_start_iteration(ms(400), ms(300)));
+        assert!(!budget.can_start_iteration(ms(500), ms(300)));
+        assert!(!budget.can_start_iteration(ms(1001), Duration::ZERO));
+    }
+}
@@ -0,0 +1,373 @@
+use std::mem;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicU8, Ordering};
+
+use crate::action::Action;
+use crate::bitboard::{coords, square};
+use crate::state::PT;
+
+pub const DEFAULT_TT_SIZE_MB: usize = 16;
+pub const MAX_TT_SIZE_MB: usize = 4096;
+
+// What a stored utility says about the real utility of the position, which depends on whether the
+// search that produced it failed low, failed high or ended inside the alpha-beta window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // The real utility is at least the stored one.
+    Lower,
+    // The real utility is at most the stored one.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: u32,
+    pub utility: i32,
+    pub bound: Bound,
+    pub action: Option<Action>,
+    age: u8,
+}
+
+const PROMOTION_TYPES: [PT; 4] = [
+    PT::Queen,
+    PT::Rook { has_moved: true },
+    PT::Bishop,
+    PT::Knight,
+];
+
+// Packs an action into 17 bits: the kind of action, the start and end squares and the piece a
+// pawn promotes to. Zero means no action.
+fn encode_action(action: Option<Action>) -> u64 {
+    let action = match action {
+        Some(action) => action,
+        None => return 0,
+    };
+    let kind = match action {
+        Action::Jump { .. } => 1,
+        Action::Capture { .. } => 2,
+        Action::Castling { .. } => 3,
+        Action::Promotion { .. } => 4,
+        Action::Enpassant { .. } => 5,
+        Action::Tie => return 6,
+    };
+    let (s_y, s_x) = action.get_main_coords();
+    let (e_y, e_x) = action.get_end_coords();
+    let promotion = match action {
+        Action::Promotion { to_piece, .. } => PROMOTION_TYPES
+            .iter()
+            .position(|t| *t == to_piece)
+            .unwrap_or(0),
+        _ => 0,
+    };
+
+    kind | (square(s_y, s_x) as u64) << 3
+        | (square(e_y, e_x) as u64) << 9
+        | (promotion as u64) << 15
+}
+
+fn decode_action(bits: u64) -> Option<Action> {
+    let (s_y, s_x) = coords((bits >> 3) as usize & 63);
+    let (e_y, e_x) = coords((bits >> 9) as usize & 63);
+    Some(match bits & 7 {
+        1 => Action::Jump { s_y, s_x, e_y, e_x },
+        2 => Action::Capture { s_y, s_x, e_y, e_x },
+        3 => Action::Castling {
+            s_y,
+            s_x,
+            queenside: e_x == 2,
+        },
+        4 => Action::Promotion {
+            s_y,
+            s_x,
+            e_y,
+            e_x,
+            to_piece: PROMOTION_TYPES[(bits >> 15) as usize & 3],
+        },
+        5 => Action::Enpassant { s_y, s_x, e_y, e_x },
+        6 => Action::Tie,
+        _ => return None,
+    })
+}
+
+// Everything but the key and the utility, in one word: the action in the lowest 17 bits, then
+// the bound, which is never zero so that an empty slot can be told apart, the age and the depth.
+fn encode_data(entry: &Entry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 1,
+        Bound::Lower => 2,
+        Bound::Upper => 3,
+    };
+    encode_action(entry.action)
+        | bound << 17
+        | (entry.age as u64) << 19
+        | (entry.depth as u64) << 32
+}
+
+// A slot is written and read one word at a time by several search threads without locking. The
+// check word is the key mixed with the other two, so that a slot whose words come from different
+// writes no longer matches its key and is treated as empty.
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,
+    utility: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> Option<Entry> {
+        let check = self.check.load(Ordering::Relaxed);
+        let utility = self.utility.load(Ordering::Relaxed);
+        let data = self.data.load(Ordering::Relaxed);
+        let bound = match (data >> 17) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None,
+        };
+
+        Some(Entry {
+            key: check ^ utility ^ data,
+            depth: (data >> 32) as u32,
+            utility: utility as i32,
+            bound,
+            action: decode_action(data & 0x1ffff),
+            age: (data >> 19) as u8,
+        })
+    }
+
+    fn save(&self, entry: &Entry) {
+        let utility = entry.utility as u64;
+        let data = encode_data(entry);
+        self.check
+            .store(entry.key ^ utility ^ data, Ordering::Relaxed);
+        self.utility.store(utility, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    fn clear(&self) {
+        self.check.store(0, Ordering::Relaxed);
+        self.utility.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
+// Each bucket keeps the deepest result for its slot, which is expensive to recompute, and the most
+// recent one, which is the most likely to be probed again.
+#[derive(Default)]
+struct Bucket {
+    depth_preferred: Slot,
+    always_replace: Slot,
+}
+
+// Shared by all threads of a search, which is why every method takes &self.
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    age: AtomicU8,
+    // What a draw was worth to White in the latest search.
+    white_draw: AtomicI32,
+}
+
+impl TranspositionTable {
+    // Uses the largest power-of-two number of buckets that fits in size_mb megabytes, so that a key
+    // can be mapped to its bucket with a mask.
+    pub fn new(size_mb: usize) -> Self {
+        let bytes = size_mb.max(1) * 1024 * 1024;
+        let max_buckets = (bytes / mem::size_of::<Bucket>()).max(1);
+        let len = 1 << (usize::BITS - 1 - max_buckets.leading_zeros());
+
+        TranspositionTable {
+            buckets: (0..len).map(|_| Bucket::default()).collect(),
+            age: AtomicU8::new(0),
+            white_draw: AtomicI32::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.depth_preferred.clear();
+            bucket.always_replace.clear();
+        }
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    // Called at the start of every search, so that results of earlier searches are replaced first.
+    // Stored utilities include the worth of the draws below them, which depends on the side the
+    // search is for once there is contempt, so they are dropped when white_draw changes.
+    pub fn new_search(&self, white_draw: i32) {
+        if self.white_draw.swap(white_draw, Ordering::Relaxed) != white_draw {
+            self.clear();
+        }
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bucket_index(&self, key: u64) -> usize {
+        key as usize & (self.buckets.len() - 1)
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let bucket = &self.buckets[self.bucket_index(key)];
+        [&bucket.depth_preferred, &bucket.always_replace]
+            .iter()
+            .filter_map(|slot| slot.load())
+            .find(|entry| entry.key == key)
+    }
+
+    pub fn store(&self, key: u64, depth: u32, utility: i32, bound: Bound, action: Option<Action>) {
+        let age = self.age.load(Ordering::Relaxed);
+        let bucket = &self.buckets[self.bucket_index(key)];
+        let entry = Entry {
+            key,
+            depth,
+            utility,
+            bound,
+            action,
+            age,
+        };
+
+        let replace_deepest = match bucket.depth_preferred.load() {
+            None => true,
+            Some(old) => old.key == key || old.age != age || old.depth <= depth,
+        };
+        if replace_deepest {
+            bucket.depth_preferred.save(&entry);
+        } else {
+            bucket.always_replace.save(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_round_trip() {
+        let mut all_actions = vec![
+            Action::Jump {
+                s_y: 0,
+                s_x: 0,
+                e_y: 7,
+                e_x: 7,
+            },
+            Action::Jump {
+                s_y: 7,
+                s_x: 7,
+                e_y: 0,
+                e_x: 0,
+            },
+            Action::Capture {
+                s_y: 4,
+                s_x: 3,
+                e_y: 3,
+                e_x: 4,
+            },
+            Action::Enpassant {
+                s_y: 3,
+                s_x: 4,
+                e_y: 2,
+                e_x: 5,
+            },
+            Action::Tie,
+        ];
+        for (s_y, queenside) in [(7, false), (7, true), (0, false), (0, true)].iter() {
+            all_actions.push(Action::Castling {
+                s_y: *s_y,
+                s_x: 4,
+                queenside: *queenside,
+            });
+        }
+        for to_piece in PROMOTION_TYPES.iter() {
+            all_actions.push(Action::Promotion {
+                s_y: 1,
+                s_x: 6,
+                e_y: 0,
+                e_x: 7,
+                to_piece: *to_piece,
+            });
+            all_actions.push(Action::Promotion {
+                s_y: 6,
+                s_x: 0,
+                e_y: 7,
+                e_x: 0,
+                to_piece: *to_piece,
+            });
+        }
+
+        for action in all_actions {
+            let bits = encode_action(Some(action));
+            assert!(bits < 1 << 17);
+            assert_eq!(decode_action(bits), Some(action));
+        }
+        assert_eq!(encode_action(None), 0);
+        assert_eq!(decode_action(0), None);
+    }
+
+    #[test]
+    fn utilities_and_bounds() {
+        let tt = TranspositionTable::new(1);
+        let action = Some(Action::Tie);
+        let cases = [
+            (1, 0, Bound::Exact),
+            (2, -350, Bound::Upper),
+            (3, 29_995, Bound::Lower),
+            (4, -29_995, Bound::Exact),
+            (5, i32::MIN + 1, Bound::Upper),
+            (6, i32::MAX - 1, Bound::Lower),
+        ];
+        for (key, utility, bound) in cases.iter() {
+            tt.store(*key, 7, *utility, *bound, action);
+        }
+        for (key, utility, bound) in cases.iter() {
+            let entry = tt.probe(*key).unwrap();
+            assert_eq!(
+                (
+                    entry.key,
+                    entry.depth,
+                    entry.utility,
+                    entry.bound,
+                    entry.action
+                ),
+                (*key, 7, *utility, *bound, action)
+            );
+        }
+        assert!(tt.probe(7).is_none());
+
+        tt.clear();
+        assert!(tt.probe(1).is_none());
+    }
+
+    #[test]
+    fn replacement() {
+        let tt = TranspositionTable::new(1);
+        // Keys that only differ above the bucket index share a bucket.
+        let key = |i: u64| 5 | i << 40;
+        let depth = |key| tt.probe(key).map(|entry: Entry| entry.depth);
+
+        tt.new_search(0);
+        tt.store(key(1), 8, 0, Bound::Exact, None);
+        tt.store(key(2), 3, 0, Bound::Exact, None);
+        assert_eq!((depth(key(1)), depth(key(2))), (Some(8), Some(3)));
+
+        // A shallower result of the same search only replaces the most recent one.
+        tt.store(key(3), 5, 0, Bound::Exact, None);
+        assert_eq!(depth(key(2)), None);
+        assert_eq!((depth(key(1)), depth(key(3))), (Some(8), Some(5)));
+
+        // The deepest one is replaced by a deeper result, or by any result of the same position.
+        tt.store(key(1), 2, 0, Bound::Lower, None);
+        assert_eq!(depth(key(1)), Some(2));
+        tt.store(key(4), 9, 0, Bound::Exact, None);
+        assert_eq!((depth(key(1)), depth(key(4))), (None, Some(9)));
+
+        // Results of an earlier search are replaced by anything.
+        tt.new_search(0);
+        tt.store(key(5), 1, 0, Bound::Exact, None);
+        assert_eq!((depth(key(4)), depth(key(5))), (None, Some(1)));
+        assert_eq!(depth(key(3)), Some(5));
+
+        // So is all of it once draws are worth something else.
+        tt.new_search(20);
+        assert_eq!((depth(key(3)), depth(key(5))), (None, None));
+    }
+}
@@ -0,0 +1,420 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::action::Action;
+use crate::actions::actions;
+use crate::ai::{
+    ai_move, fallback_action, MoveSource, SearchInfo, SearchOptions, DEFAULT_CONTEMPT,
+};
+use crate::book::{BookSelection, OpeningBook, DEFAULT_BOOK_DEPTH};
+use crate::result::result;
+use crate::state::{State, PC};
+use crate::syzygy::Tablebase;
+use crate::time_manager::TimeBudget;
+use crate::tt::{TranspositionTable, DEFAULT_TT_SIZE_MB, MAX_TT_SIZE_MB};
+
+const ENGINE_NAME: &str = "rust_chess";
+const ENGINE_AUTHOR: &str = "Vladimir Maksimovski";
+
+// Iterative deepening stops at this depth when "go" does not specify one.
+const MAX_SEARCH_DEPTH: u32 = 64;
+const MAX_BOOK_DEPTH: usize = 1000;
+const MAX_THREADS: usize = 256;
+const MAX_CONTEMPT: i32 = 1000;
+
+#[derive(Default)]
+struct GoParams {
+    depth: Option<u32>,
+    movetime: Option<u64>,
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    movestogo: Option<u64>,
+    infinite: bool,
+}
+
+fn parse_go(args: &[&str]) -> GoParams {
+    let mut params = GoParams::default();
+    let mut iter = args.iter();
+    while let Some(&arg) = iter.next() {
+        // Some GUIs send negative clock times once a side is in time trouble.
+        let mut number = || {
+            iter.next()
+                .and_then(|v| v.parse::<i64>().ok())
+                .map(|v| v.max(0) as u64)
+        };
+        match arg {
+            "depth" => params.depth = number().map(|d| d as u32),
+            "movetime" => params.movetime = number(),
+            "wtime" => params.wtime = number(),
+            "btime" => params.btime = number(),
+            "winc" => params.winc = number(),
+            "binc" => params.binc = number(),
+            "movestogo" => params.movestogo = number(),
+            "infinite" => params.infinite = true,
+            _ => (),
+        }
+    }
+
+    params
+}
+
+fn time_budget(params: &GoParams, turn: PC) -> Option<TimeBudget> {
+    if params.infinite {
+        return None;
+    }
+    if let Some(movetime) = params.movetime {
+        return Some(TimeBudget::fixed(Duration::from_millis(movetime)));
+    }
+
+    let (time, increment) = match turn {
+        PC::White => (params.wtime?, params.winc.unwrap_or(0)),
+        PC::Black => (params.btime?, params.binc.unwrap_or(0)),
+    };
+
+    Some(TimeBudget::from_clock(
+        Duration::from_millis(time),
+        Duration::from_millis(increment),
+        params.movestogo.map(|m| m as u32),
+    ))
+}
+
+fn parse_position(args: &[&str]) -> Option<State> {
+    let (mut state, rest) = match args.first() {
+        Some(&"startpos") => (State::new(), &args[1..]),
+        Some(&"fen") => {
+            let end = args
+                .iter()
+                .position(|&a| a == "moves")
+                .unwrap_or(args.len());
+            (State::from_fen(&args[1..end].join(" ")).ok()?, &args[end..])
+        }
+        _ => return None,
+    };
+
+    if rest.first() == Some(&"moves") {
+        for m in &rest[1..] {
+            let action = Action::from_uci(&state, m)?;
+            state = result(&state, &action);
+        }
+    }
+
+    Some(state)
+}
+
+// Splits "name <id> value <x>" into the option name and its value, both of which may contain
+// spaces.
+fn parse_setoption(args: &[&str]) -> Option<(String, String)> {
+    let value_index = args.iter().position(|&a| a == "value");
+    let name_end = value_index.unwrap_or(args.len());
+    if args.first() != Some(&"name") || name_end < 2 {
+        return None;
+    }
+
+    let value = match value_index {
+        Some(i) => args[i + 1..].join(" "),
+        None => String::new(),
+    };
+    Some((args[1..name_end].join(" "), value))
+}
+
+fn info_line(info: &SearchInfo) -> String {
+    let millis = info.elapsed.as_millis() as u64;
+    let score = match info.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", info.utility),
+    };
+    format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        info.nodes * 1000 / millis.max(1),
+        millis,
+        info.pv
+            .iter()
+            .map(|a| a.to_uci())
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+struct RunningSearch {
+    stop_tx: Sender<()>,
+    // Releases the bestmove of an infinite search, which may only be sent after "stop".
+    release_tx: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl RunningSearch {
+    // Stops the search and waits until its bestmove has been printed.
+    fn stop(self) {
+        let _ = self.stop_tx.send(());
+        let _ = self.release_tx.send(());
+        let _ = self.handle.join();
+    }
+}
+
+fn start_search(
+    state: State,
+    params: &GoParams,
+    tt: Arc<TranspositionTable>,
+    book: Option<Arc<OpeningBook>>,
+    tablebase: Option<Arc<Tablebase>>,
+    options: SearchOptions,
+) -> RunningSearch {
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel();
+    let infinite = params.infinite;
+    let max_depth = params.depth.unwrap_or(MAX_SEARCH_DEPTH);
+    let budget = time_budget(params, state.turn);
+
+    let handle = thread::spawn(move || {
+        // Without a legal move there is nothing to search, and the null move is sent instead.
+        let mut best_action = None;
+        if actions(&state).iter().any(|a| *a != Action::Tie) {
+            let (tx, rx) = mpsc::channel();
+            let search_state = state.clone();
+            let search_tt = tt.clone();
+            let searcher = thread::spawn(move || {
+                ai_move(
+                    search_state,
+                    tx,
+                    max_depth,
+                    budget,
+                    stop_rx,
+                    book.as_deref(),
+                    tablebase.as_deref(),
+                    options,
+                    &search_tt,
+                );
+            });
+
+            for info in rx {
+                if info.source != MoveSource::Search {
+                    println!("info string {} move {}", info.source, info.action.to_uci());
+                } else {
+                    println!("{}", info_line(&info));
+                }
+                // UCI has no way to claim a draw, so the best move on the board is played instead.
+                best_action = Some(info.board_action);
+            }
+            let _ = searcher.join();
+            if best_action.is_none() {
+                best_action = fallback_action(&state, &tt);
+            }
+        }
+
+        if infinite {
+            let _ = release_rx.recv();
+        }
+        match best_action {
+            Some(action) => println!("bestmove {}", action.to_uci()),
+            None => println!("bestmove 0000"),
+        }
+    });
+
+    RunningSearch {
+        stop_tx,
+        release_tx,
+        handle,
+    }
+}
+
+pub fn uci_loop() {
+    let mut state = State::new();
+    let mut search: Option<RunningSearch> = None;
+    // Kept between searches, so that later moves can reuse what earlier searches found.
+    let mut tt = Arc::new(TranspositionTable::new(DEFAULT_TT_SIZE_MB));
+    // The book is only used with OwnBook, but keeps its settings while switched off.
+    let mut own_book = false;
+    let mut book: Option<Arc<OpeningBook>> = None;
+    let mut book_depth = DEFAULT_BOOK_DEPTH;
+    let mut book_selection = BookSelection::WeightedRandom;
+    let mut tablebase: Option<Arc<Tablebase>> = None;
+    let mut search_options = SearchOptions::default();
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        match command {
+            "uci" => {
+                println!("id name {}", ENGINE_NAME);
+                println!("id author {}", ENGINE_AUTHOR);
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_TT_SIZE_MB, MAX_TT_SIZE_MB
+                );
+                println!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                );
+                println!(
+                    "option name Contempt type spin default {} min {} max {}",
+                    DEFAULT_CONTEMPT, -MAX_CONTEMPT, MAX_CONTEMPT
+                );
+                println!("option name OwnBook type check default false");
+                println!("option name Book File type string default <empty>");
+                println!(
+                    "option name Book Depth type spin default {} min 0 max {}",
+                    DEFAULT_BOOK_DEPTH, MAX_BOOK_DEPTH
+                );
+                println!(
+                    "option name Book Selection type combo default Random var Random var Best"
+                );
+                println!("option name SyzygyPath type string default <empty>");
+                for name in SearchOptions::SWITCHES.iter() {
+                    println!("option name {} type check default true", name);
+                }
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                if let Some(s) = search.take() {
+                    s.stop();
+                }
+                state = State::new();
+                tt.clear();
+            }
+            "setoption" => match parse_setoption(args) {
+                Some((name, value)) if name.eq_ignore_ascii_case("Hash") => {
+                    match value.parse::<usize>() {
+                        Ok(size_mb) => {
+                            if let Some(s) = search.take() {
+                                s.stop();
+                            }
+                            // Only the stopped search still had the old table.
+                            let size_mb = size_mb.clamp(1, MAX_TT_SIZE_MB);
+                            tt = Arc::new(TranspositionTable::new(size_mb));
+                        }
+                        Err(_) => println!("info string invalid Hash size: {}", value),
+                    }
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("Threads") => {
+                    match value.parse::<usize>() {
+                        Ok(threads) => search_options.threads = threads.clamp(1, MAX_THREADS),
+                        Err(_) => println!("info string invalid Threads: {}", value),
+                    }
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("Contempt") => {
+                    match value.parse::<i32>() {
+                        Ok(contempt) => {
+                            search_options.contempt = contempt.clamp(-MAX_CONTEMPT, MAX_CONTEMPT)
+                        }
+                        Err(_) => println!("info string invalid Contempt: {}", value),
+                    }
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("OwnBook") => {
+                    own_book = value == "true";
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("Book File") => {
+                    if value.is_empty() || value == "<empty>" {
+                        book = None;
+                    } else {
+                        match OpeningBook::open(&value) {
+                            Ok(mut new_book) => {
+                                new_book.depth = book_depth;
+                                new_book.selection = book_selection;
+                                println!(
+                                    "info string loaded {} book entries from {}",
+                                    new_book.len(),
+                                    value
+                                );
+                                book = Some(Arc::new(new_book));
+                            }
+                            Err(e) => println!("info string could not read book {}: {}", value, e),
+                        }
+                    }
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("Book Depth") => {
+                    match value.parse::<usize>() {
+                        Ok(depth) => {
+                            book_depth = depth.min(MAX_BOOK_DEPTH);
+                            if let Some(book) = &mut book {
+                                Arc::make_mut(book).depth = book_depth;
+                            }
+                        }
+                        Err(_) => println!("info string invalid Book Depth: {}", value),
+                    }
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("Book Selection") => {
+                    book_selection = match value.as_str() {
+                        "Best" => BookSelection::BestWeight,
+                        _ => BookSelection::WeightedRandom,
+                    };
+                    if let Some(book) = &mut book {
+                        Arc::make_mut(book).selection = book_selection;
+                    }
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("SyzygyPath") => {
+                    if let Some(s) = search.take() {
+                        s.stop();
+                    }
+                    if value.is_empty() || value == "<empty>" {
+                        tablebase = None;
+                    } else {
+                        match Tablebase::open(&value) {
+                            Ok(new_tablebase) => {
+                                println!(
+                                    "info string found {} tablebases of up to {} pieces",
+                                    new_tablebase.len(),
+                                    new_tablebase.max_pieces()
+                                );
+                                tablebase = Some(Arc::new(new_tablebase));
+                            }
+                            Err(e) => {
+                                println!("info string could not read tablebases {}: {}", value, e)
+                            }
+                        }
+                    }
+                }
+                Some((name, value)) => match search_options.switch_mut(&name) {
+                    Some(switch) => *switch = value == "true",
+                    None => println!("info string unknown option: {}", line),
+                },
+                None => println!("info string unknown option: {}", line),
+            },
+            "position" => match parse_position(args) {
+                Some(new_state) => state = new_state,
+                None => println!("info string invalid position: {}", line),
+            },
+            "go" => {
+                if let Some(s) = search.take() {
+                    s.stop();
+                }
+                let book = book.clone().filter(|_| own_book);
+                search = Some(start_search(
+                    state.clone(),
+                    &parse_go(args),
+                    tt.clone(),
+                    book,
+                    tablebase.clone(),
+                    search_options,
+                ));
+            }
+            "stop" => {
+                if let Some(s) = search.take() {
+                    s.stop();
+                }
+            }
+            "quit" => break,
+            _ => (),
+        }
+    }
+
+    if let Some(s) = search.take() {
+        s.stop();
+    }
+}
@@ -1,23 +1,34 @@
 use std::collections::HashMap;
 use std::ops::DerefMut;
 use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use fxhash::FxHashMap;
 use sfml::audio::{Sound, SoundBuffer};
 use sfml::graphics::Transformable;
-use sfml::graphics::{CircleShape, Color, RenderTarget, RenderWindow, Shape, Sprite, Texture};
+use sfml::graphics::{
+    CircleShape, Color, Font, RectangleShape, RenderTarget, RenderWindow, Shape, Sprite, Text,
+    Texture,
+};
 use sfml::window::mouse::Button;
 use sfml::window::{ContextSettings, Event, Key, Style, VideoMode};
 use sfml::SfBox;
 
 use crate::action::Action;
 use crate::actions::{actions_for_location, validate_action};
-use crate::ai::ai_move;
+use crate::ai::{ai_move, MoveSource, SearchInfo, SearchOptions};
+use crate::book::OpeningBook;
+use crate::clock::{format_clock, ChessClock, TimeControl};
+use crate::pgn::Pgn;
 use crate::result::result;
+use crate::san::line_to_san;
 use crate::state::{Piece, State, PC, PT};
-use crate::value::{value, Status};
+use crate::syzygy::Tablebase;
+use crate::time_manager::TimeBudget;
+use crate::tt::{TranspositionTable, DEFAULT_TT_SIZE_MB};
+use crate::value::{timeout_result, value, GameResult, Termination};
 
 enum UiState {
     PlayerThinking,
@@ -26,7 +37,7 @@ enum UiState {
         x: usize,
         targets: Vec<Action>,
     },
-    AIThinking(Receiver<(u32, Action)>, Sender<()>),
+    AIThinking(Receiver<SearchInfo>, Sender<()>),
     Done,
 }
 
@@ -36,11 +47,36 @@ pub enum Thinker {
 }
 
 impl Thinker {
-    fn to_ui_state(&self, state: &State, ai_lookahead_depth: u32) -> UiState {
+    fn name(&self) -> &'static str {
+        match self {
+            Thinker::Player => "Player",
+            Thinker::AI => "rust_chess",
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn to_ui_state(
+        &self,
+        state: &State,
+        ai_lookahead_depth: u32,
+        time_budget: Option<TimeBudget>,
+        tt: &Arc<TranspositionTable>,
+        book: &Option<Arc<OpeningBook>>,
+        tablebase: &Option<Arc<Tablebase>>,
+        options: SearchOptions,
+    ) -> UiState {
         match self {
             Thinker::Player => UiState::PlayerThinking,
             Thinker::AI => {
-                let (tx, done_rx) = launch_ai_thread(state.clone(), ai_lookahead_depth);
+                let (tx, done_rx) = launch_ai_thread(
+                    state.clone(),
+                    ai_lookahead_depth,
+                    time_budget,
+                    tt.clone(),
+                    book.clone(),
+                    tablebase.clone(),
+                    options,
+                );
                 UiState::AIThinking(tx, done_rx)
             }
         }
@@ -135,17 +171,48 @@ impl ChessTextures {
     }
 }
 
-fn launch_ai_thread(state: State, max_depth: u32) -> (Receiver<(u32, Action)>, Sender<()>) {
+fn launch_ai_thread(
+    state: State,
+    max_depth: u32,
+    time_budget: Option<TimeBudget>,
+    tt: Arc<TranspositionTable>,
+    book: Option<Arc<OpeningBook>>,
+    tablebase: Option<Arc<Tablebase>>,
+    options: SearchOptions,
+) -> (Receiver<SearchInfo>, Sender<()>) {
     let (tx, rx) = mpsc::channel();
     let (done_tx, done_rx) = mpsc::channel();
-    let mut move_cache = FxHashMap::default();
 
     thread::spawn(move || {
-        ai_move(state, tx, max_depth, done_rx, &mut move_cache);
+        ai_move(
+            state,
+            tx,
+            max_depth,
+            time_budget,
+            done_rx,
+            book.as_deref(),
+            tablebase.as_deref(),
+            options,
+            &tt,
+        );
     });
     (rx, done_tx)
 }
 
+// Ends the turn on the clock, if there is one, and stops it when the game is over.
+fn press_clock(clock: &mut Option<ChessClock>, game_result: GameResult) {
+    if let Some(clock) = clock {
+        clock.press();
+        if game_result != GameResult::Running {
+            clock.stop();
+        }
+    }
+}
+
+fn time_control_of(clock: &Option<ChessClock>) -> Option<&TimeControl> {
+    clock.as_ref().map(|c| c.time_control())
+}
+
 fn get_board_coordinates(coords: (u32, u32), window_size: u32) -> Option<(usize, usize)> {
     let (x, y) = coords;
     if x >= window_size || y >= window_size {
@@ -161,21 +228,78 @@ fn get_board_coordinates(coords: (u32, u32), window_size: u32) -> Option<(usize,
     }
 }
 
-pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
-    let mut history = vec![State::new()];
+fn save_game(
+    color_assignments: &HashMap<PC, Thinker>,
+    initial_state: &State,
+    played_actions: &[Action],
+    game_result: GameResult,
+    time_control: Option<&TimeControl>,
+) {
+    let mut pgn = Pgn::new(initial_state.clone());
+    pgn.set_tag("Event", "Casual game");
+    pgn.set_tag("Date", &chrono::Local::now().format("%Y.%m.%d").to_string());
+    pgn.set_tag("Round", "-");
+    pgn.set_tag("White", color_assignments[&PC::White].name());
+    pgn.set_tag("Black", color_assignments[&PC::Black].name());
+    if let Some(tag) = time_control.and_then(|t| t.pgn_tag()) {
+        pgn.set_tag("TimeControl", &tag);
+    }
+    for action in played_actions {
+        pgn.push_move(*action, None);
+    }
+    if let Some(last_move) = pgn.moves.last_mut() {
+        last_move.comment = Some(game_result.to_string());
+    }
+    pgn.set_result(game_result);
 
+    let file_name = format!("game-{}.pgn", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    match std::fs::write(&file_name, pgn.to_string()) {
+        Ok(()) => log::info!("Saved game to {}", file_name),
+        Err(e) => log::error!("Could not save game to {}: {}", file_name, e),
+    }
+}
+
+// `history` holds the states reached by `played_actions`, starting with the initial state. Games
+// with a `time_control` are played with clocks, shown below the board.
+pub fn ui_routine(
+    color_assignments: HashMap<PC, Thinker>,
+    mut history: Vec<State>,
+    mut played_actions: Vec<Action>,
+    time_control: Option<TimeControl>,
+    book: Option<OpeningBook>,
+    tablebase: Option<Tablebase>,
+    options: SearchOptions,
+) {
     const AI_LOOKAHEAD_DEPTH: u32 = 7;
+    // With clocks, the AI searches for as long as its time allows instead.
+    const TIMED_AI_MAX_DEPTH: u32 = 64;
 
     const WINDOWSIZE: u32 = 1200;
+    const CLOCK_BAR_HEIGHT: u32 = 80;
+    // Clocks are drawn in red once they get below this.
+    const LOW_TIME: Duration = Duration::from_secs(10);
+    const TITLE: &str = "Vlad's Chess";
     let cell_size = WINDOWSIZE as f32 / 8.;
 
+    let mut clock = time_control.map(ChessClock::new);
+    let ai_max_depth = if clock.is_some() {
+        TIMED_AI_MAX_DEPTH
+    } else {
+        AI_LOOKAHEAD_DEPTH
+    };
+    let window_height = if clock.is_some() {
+        WINDOWSIZE + CLOCK_BAR_HEIGHT
+    } else {
+        WINDOWSIZE
+    };
+
     let context_settings = ContextSettings {
         antialiasing_level: 2,
         ..Default::default()
     };
     let mut window = RenderWindow::new(
-        VideoMode::new(WINDOWSIZE, WINDOWSIZE, 32),
-        "Vlad's Chess",
+        VideoMode::new(WINDOWSIZE, window_height, 32),
+        TITLE,
         Style::CLOSE,
         &context_settings,
     );
@@ -183,6 +307,7 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
 
     let buffer = SoundBuffer::from_file("media/Move.ogg").unwrap();
     let mut sound = Sound::with_buffer(&buffer);
+    let font = Font::from_file("media/DejaVuSansMono.ttf").unwrap();
 
     let chess_textures = ChessTextures::new();
     let generate_sprite_board = |state: &State| {
@@ -212,11 +337,32 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
     };
     let mut sprite_board = generate_sprite_board(&history[history.len() - 1]);
 
-    let mut last_start = None;
-    let mut last_end = None;
+    let mut last_start = played_actions.last().map(|a| a.get_main_coords());
+    let mut last_end = played_actions.last().map(|a| a.get_end_coords());
 
-    let mut ui_state =
-        color_assignments[&history[0].turn].to_ui_state(&history[0], AI_LOOKAHEAD_DEPTH);
+    // Kept for the whole game, so that the AI can reuse what it found on earlier moves.
+    let tt = Arc::new(TranspositionTable::new(DEFAULT_TT_SIZE_MB));
+    let book = book.map(Arc::new);
+    let tablebase = tablebase.map(Arc::new);
+
+    let current_state = &history[history.len() - 1];
+    let mut ui_state = match value(current_state) {
+        GameResult::Running => {
+            if let Some(clock) = &mut clock {
+                clock.start(current_state.turn);
+            }
+            color_assignments[&current_state.turn].to_ui_state(
+                current_state,
+                ai_max_depth,
+                clock.as_ref().map(|c| c.time_budget(current_state.turn)),
+                &tt,
+                &book,
+                &tablebase,
+                options,
+            )
+        }
+        _ => UiState::Done,
+    };
 
     let mut is_player_done_waiting = false;
     let mut latest_move = None;
@@ -251,8 +397,13 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
                         ui_state = UiState::PlayerThinking;
 
                         history.pop();
+                        played_actions.pop();
                         let old_state = &history[history.len() - 1];
                         sprite_board = generate_sprite_board(old_state);
+                        // The time spent on the taken back moves is not given back.
+                        if let Some(clock) = &mut clock {
+                            clock.start(old_state.turn);
+                        }
 
                         last_start = None;
                         last_end = None;
@@ -265,11 +416,59 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
                 }) => {
                     is_player_done_waiting = true;
                 }
+                Some(Event::KeyPressed { code: Key::R, .. }) => {
+                    // The player to move resigns.
+                    let is_player_turn = matches!(
+                        ui_state,
+                        UiState::PlayerThinking | UiState::PlayerHighlighted { .. }
+                    );
+                    if is_player_turn {
+                        let game_result = GameResult::win_for(
+                            display_state.turn.opponent(),
+                            Termination::Resignation,
+                        );
+                        println!("{}", game_result);
+                        if let Some(clock) = &mut clock {
+                            clock.stop();
+                        }
+                        save_game(
+                            &color_assignments,
+                            &history[0],
+                            &played_actions,
+                            game_result,
+                            time_control_of(&clock),
+                        );
+                        ui_state = UiState::Done;
+                    }
+                }
                 Some(_) => (),
                 None => break,
             }
         }
 
+        let flagged = match (&ui_state, &clock) {
+            (UiState::Done, _) | (_, None) => None,
+            (_, Some(clock)) => clock.flagged(),
+        };
+        if let Some(c) = flagged {
+            if let UiState::AIThinking(_, done_tx) = &ui_state {
+                let _ = done_tx.send(());
+            }
+            let game_result = timeout_result(display_state, c);
+            println!("{}", game_result);
+            if let Some(clock) = &mut clock {
+                clock.stop();
+            }
+            save_game(
+                &color_assignments,
+                &history[0],
+                &played_actions,
+                game_result,
+                time_control_of(&clock),
+            );
+            ui_state = UiState::Done;
+        }
+
         match ui_state {
             UiState::PlayerThinking => {
                 for coords in release_locations {
@@ -355,32 +554,46 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
                                 promotion_choice,
                             ) {
                                 if validate_action(display_state, &action) {
-                                    println!("Player move: {}", action.to_string(display_state));
+                                    println!("Player move: {}", action.to_san(display_state));
                                     let new_state = result(display_state, &action);
                                     log::info!("State: {:?}", new_state);
                                     last_start = Some(action.get_main_coords());
                                     last_end = Some(action.get_end_coords());
 
                                     sprite_board = generate_sprite_board(&new_state);
-                                    ui_state = match value(&new_state) {
-                                        Status::Running => color_assignments[&new_state.turn]
-                                            .to_ui_state(&new_state, AI_LOOKAHEAD_DEPTH),
-                                        Status::BlackWin => {
-                                            println!("Black win!");
-                                            UiState::Done
-                                        }
-                                        Status::WhiteWin => {
-                                            println!("White win!");
-                                            UiState::Done
-                                        }
-                                        Status::Tie => {
-                                            println!("Tie!");
+                                    let game_result = value(&new_state);
+                                    press_clock(&mut clock, game_result);
+                                    ui_state = match game_result {
+                                        GameResult::Running => color_assignments[&new_state.turn]
+                                            .to_ui_state(
+                                                &new_state,
+                                                ai_max_depth,
+                                                clock
+                                                    .as_ref()
+                                                    .map(|c| c.time_budget(new_state.turn)),
+                                                &tt,
+                                                &book,
+                                                &tablebase,
+                                                options,
+                                            ),
+                                        _ => {
+                                            println!("{}", game_result);
                                             UiState::Done
                                         }
                                     };
 
                                     sound.play();
                                     history.push(new_state);
+                                    played_actions.push(action);
+                                    if let UiState::Done = ui_state {
+                                        save_game(
+                                            &color_assignments,
+                                            &history[0],
+                                            &played_actions,
+                                            game_result,
+                                            time_control_of(&clock),
+                                        );
+                                    }
                                     is_player_done_waiting = false;
                                 } else {
                                     ui_state = UiState::PlayerThinking;
@@ -393,34 +606,71 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
                 }
             }
             UiState::AIThinking(ref tx, ref done_rx) => {
-                while let Ok((moves_ahead, ai_action)) = tx.try_recv() {
-                    latest_move = Some((moves_ahead, ai_action));
+                // The search is over once it hangs up, either at its maximum depth or when its
+                // time is up.
+                let mut search_finished = false;
+                loop {
+                    match tx.try_recv() {
+                        Ok(info) if info.source != MoveSource::Search => {
+                            window.set_title(&format!(
+                                "{} - {}: {}",
+                                TITLE,
+                                info.source,
+                                line_to_san(display_state, &info.pv)
+                            ));
+                            latest_move = Some(info.action);
+                        }
+                        Ok(info) => {
+                            // The score is in pawns, from the point of view of the AI.
+                            let score = match info.mate_in() {
+                                Some(moves) if moves > 0 => format!("mate in {}", moves),
+                                Some(moves) => format!("mated in {}", -moves),
+                                None => format!("{:+.2}", info.utility as f64 / 100.),
+                            };
+                            window.set_title(&format!(
+                                "{} - depth {}, {}: {}",
+                                TITLE,
+                                info.depth,
+                                score,
+                                line_to_san(display_state, &info.pv)
+                            ));
+                            latest_move = Some(info.action);
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            search_finished = true;
+                            break;
+                        }
+                    }
                 }
 
                 match latest_move {
                     None => (),
-                    Some((moves_ahead, ai_action)) => {
-                        if moves_ahead == AI_LOOKAHEAD_DEPTH || is_player_done_waiting {
+                    Some(ai_action) => {
+                        if search_finished || is_player_done_waiting {
                             is_player_done_waiting = false;
+                            latest_move = None;
                             let _ = done_rx.send(());
 
                             let new_state = result(display_state, &ai_action);
                             last_start = Some(ai_action.get_main_coords());
                             last_end = Some(ai_action.get_end_coords());
 
-                            ui_state = match value(&new_state) {
-                                Status::Running => color_assignments[&new_state.turn]
-                                    .to_ui_state(&new_state, AI_LOOKAHEAD_DEPTH),
-                                Status::BlackWin => {
-                                    println!("Black win!");
-                                    UiState::Done
-                                }
-                                Status::WhiteWin => {
-                                    println!("White win!");
-                                    UiState::Done
-                                }
-                                Status::Tie => {
-                                    println!("Tie!");
+                            let game_result = value(&new_state);
+                            press_clock(&mut clock, game_result);
+                            ui_state = match game_result {
+                                GameResult::Running => color_assignments[&new_state.turn]
+                                    .to_ui_state(
+                                        &new_state,
+                                        ai_max_depth,
+                                        clock.as_ref().map(|c| c.time_budget(new_state.turn)),
+                                        &tt,
+                                        &book,
+                                        &tablebase,
+                                        options,
+                                    ),
+                                _ => {
+                                    println!("{}", game_result);
                                     UiState::Done
                                 }
                             };
@@ -429,6 +679,16 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
 
                             sound.play();
                             history.push(new_state);
+                            played_actions.push(ai_action);
+                            if let UiState::Done = ui_state {
+                                save_game(
+                                    &color_assignments,
+                                    &history[0],
+                                    &played_actions,
+                                    game_result,
+                                    time_control_of(&clock),
+                                );
+                            }
                         }
                     }
                 }
@@ -479,6 +739,32 @@ pub fn ui_routine(color_assignments: HashMap<PC, Thinker>) {
             }
         }
 
+        if let Some(clock) = &clock {
+            let width = WINDOWSIZE as f32 / 2.;
+            for (i, c) in [PC::White, PC::Black].iter().enumerate() {
+                let mut background =
+                    RectangleShape::with_size((width, CLOCK_BAR_HEIGHT as f32).into());
+                background.set_position((i as f32 * width, WINDOWSIZE as f32));
+                background.set_fill_color(if clock.running_side() == Some(*c) {
+                    Color::rgb(70, 70, 70)
+                } else {
+                    Color::rgb(20, 20, 20)
+                });
+                window.draw(&background);
+
+                let remaining = clock.remaining(*c);
+                let mut text =
+                    Text::new(&format!("{:?} {}", c, format_clock(remaining)), &font, 48);
+                text.set_fill_color(if remaining < LOW_TIME {
+                    Color::rgb(255, 80, 80)
+                } else {
+                    Color::WHITE
+                });
+                text.set_position((i as f32 * width + 20., WINDOWSIZE as f32 + 10.));
+                window.draw(&text);
+            }
+        }
+
         window.display();
     }
 }
@@ -1,33 +1,160 @@
+use std::fmt;
+
 use crate::action::Action;
 use crate::actions::{any_actions, is_king_attacked};
-use crate::state::{Piece, State, PC, PT};
+use crate::bitboard::{color_index, Bitboard, BISHOP, KING, KNIGHT, PAWN, QUEEN, ROOK};
+use crate::state::{Piece, State, PC, PT, SEVENTY_FIVE_MOVE_RULE_PLIES};
+
+// How a game came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    ThreefoldRepetition,
+    FivefoldRepetition,
+    FiftyMoveRule,
+    SeventyFiveMoveRule,
+    InsufficientMaterial,
+    Resignation,
+    Timeout,
+    Agreement,
+    // The reason was not recorded, e.g. for a result read from a PGN file.
+    Unknown,
+}
 
-pub enum Status {
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Termination::Checkmate => "checkmate",
+            Termination::Stalemate => "stalemate",
+            Termination::ThreefoldRepetition => "threefold repetition",
+            Termination::FivefoldRepetition => "fivefold repetition",
+            Termination::FiftyMoveRule => "the fifty-move rule",
+            Termination::SeventyFiveMoveRule => "the seventy-five-move rule",
+            Termination::InsufficientMaterial => "insufficient material",
+            Termination::Resignation => "resignation",
+            Termination::Timeout => "timeout",
+            Termination::Agreement => "agreement",
+            Termination::Unknown => "unknown reason",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
     Running,
-    BlackWin,
-    Tie,
-    WhiteWin,
+    BlackWin(Termination),
+    Tie(Termination),
+    WhiteWin(Termination),
 }
 
-pub fn value(state: &State) -> Status {
-    if state.drawn {
-        return Status::Tie;
+impl GameResult {
+    pub fn win_for(c: PC, termination: Termination) -> GameResult {
+        match c {
+            PC::White => GameResult::WhiteWin(termination),
+            PC::Black => GameResult::BlackWin(termination),
+        }
+    }
+
+    pub fn termination(&self) -> Option<Termination> {
+        match *self {
+            GameResult::Running => None,
+            GameResult::BlackWin(t) | GameResult::Tie(t) | GameResult::WhiteWin(t) => Some(t),
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (winner, termination) = match *self {
+            GameResult::Running => return write!(f, "Game in progress"),
+            GameResult::Tie(Termination::Unknown) => return write!(f, "Draw"),
+            GameResult::Tie(Termination::Timeout) => {
+                return write!(f, "Draw by timeout against insufficient material")
+            }
+            GameResult::Tie(termination) => return write!(f, "Draw by {}", termination),
+            GameResult::BlackWin(termination) => ("Black", termination),
+            GameResult::WhiteWin(termination) => ("White", termination),
+        };
+        match termination {
+            Termination::Unknown => write!(f, "{} wins", winner),
+            Termination::Timeout => write!(f, "{} wins on time", winner),
+            _ => write!(f, "{} wins by {}", winner, termination),
+        }
+    }
+}
+
+// Squares with (y + x) even, which includes a8 and h1.
+const LIGHT_SQUARES: Bitboard = 0xaa55_aa55_aa55_aa55;
+
+// Whether no sequence of legal moves can lead to checkmate: bare kings, a single minor piece, or
+// bishops that all stand on squares of the same color.
+pub fn is_insufficient_material(state: &State) -> bool {
+    let bitboards = &state.bitboards;
+    let heavy_or_pawns = [PAWN, ROOK, QUEEN]
+        .iter()
+        .any(|&p| bitboards.of(PC::White, p) | bitboards.of(PC::Black, p) != 0);
+    if heavy_or_pawns {
+        return false;
+    }
+
+    let knights = bitboards.of(PC::White, KNIGHT) | bitboards.of(PC::Black, KNIGHT);
+    let bishops = bitboards.of(PC::White, BISHOP) | bitboards.of(PC::Black, BISHOP);
+    if (knights | bishops).count_ones() <= 1 {
+        return true;
+    }
+
+    knights == 0 && (bishops & LIGHT_SQUARES == 0 || bishops & !LIGHT_SQUARES == 0)
+}
+
+// The result when c runs out of time: the opponent wins, unless it could not checkmate by any
+// sequence of moves because only its king is left or because of insufficient material overall.
+pub fn timeout_result(state: &State, c: PC) -> GameResult {
+    let winner = c.opponent();
+    let bitboards = &state.bitboards;
+    let only_king = bitboards.colors[color_index(winner)] == bitboards.of(winner, KING);
+    if only_king || is_insufficient_material(state) {
+        GameResult::Tie(Termination::Timeout)
+    } else {
+        GameResult::win_for(winner, Termination::Timeout)
+    }
+}
+
+// What a draw claim made in state is for. A position that occurs for the third time is claimed
+// by repetition, even if the fifty-move rule applies as well.
+pub fn claimed_draw(state: &State) -> Termination {
+    match state.hash_to_occurences.get(&state.hash()) {
+        Some(&x) if x >= 3 => Termination::ThreefoldRepetition,
+        _ => Termination::FiftyMoveRule,
+    }
+}
+
+pub fn value(state: &State) -> GameResult {
+    if let Some(termination) = state.drawn {
+        return GameResult::Tie(termination);
+    }
+    if is_insufficient_material(state) {
+        return GameResult::Tie(Termination::InsufficientMaterial);
     }
 
     let actions_empty = !any_actions(state);
 
     if !actions_empty {
-        Status::Running
+        // A checkmate on the last of the seventy-five moves still stands, so this comes after
+        // looking for moves.
+        if state.halfmove_clock >= SEVENTY_FIVE_MOVE_RULE_PLIES {
+            GameResult::Tie(Termination::SeventyFiveMoveRule)
+        } else {
+            GameResult::Running
+        }
     } else {
         let king_attacked = is_king_attacked(state, state.turn);
 
         if king_attacked {
-            match state.turn.opponent() {
-                PC::Black => Status::BlackWin,
-                PC::White => Status::WhiteWin,
-            }
+            GameResult::win_for(state.turn.opponent(), Termination::Checkmate)
         } else {
-            Status::Tie
+            GameResult::Tie(Termination::Stalemate)
         }
     }
 }
@@ -120,12 +247,14 @@ fn piece_position_worth(pc: PC, pt: PT, i: usize, j: usize) -> isize {
     }
 }
 
-fn piece_value(p: &Piece, i: usize, j: usize) -> f64 {
-    PIECE_WORTH_COEFFICIENT * piece_worth(p.t) + piece_position_worth(p.c, p.t, i, j) as f64
+// In whole centipawns, so that values can be added up in any order with the same result.
+fn piece_value(p: &Piece, i: usize, j: usize) -> i32 {
+    (PIECE_WORTH_COEFFICIENT * piece_worth(p.t)).round() as i32
+        + piece_position_worth(p.c, p.t, i, j) as i32
 }
 
-pub fn heuristic(state: &State) -> f64 {
-    let mut value = 0.0;
+pub fn heuristic(state: &State) -> i32 {
+    let mut value = 0;
 
     for i in 0..8 {
         for j in 0..8 {
@@ -142,9 +271,9 @@ pub fn heuristic(state: &State) -> f64 {
     value
 }
 
-pub fn heuristic_action(state: &State, action: &Action) -> f64 {
+pub fn heuristic_action(state: &State, action: &Action) -> i32 {
     if *action == Action::Tie {
-        return 0.;
+        return 0;
     }
 
     let main_piece = action
@@ -185,7 +314,7 @@ pub fn heuristic_action(state: &State, action: &Action) -> f64 {
             to_piece,
         } => {
             let captured_value = match state.board[e_y][e_x] {
-                None => 0.,
+                None => 0,
                 Some(captured_piece) => piece_value(&captured_piece, e_y, e_x),
             };
 
@@ -200,6 +329,147 @@ pub fn heuristic_action(state: &State, action: &Action) -> f64 {
             piece_value(&main_piece, e_y, e_x) - piece_value(&main_piece, s_y, s_x)
                 + piece_value(&captured_pawn, s_y, e_x)
         }
-        Action::Tie => 0.,
+        Action::Tie => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::actions;
+    use crate::pgn::Pgn;
+    use crate::result::{inplace_result, inplace_revert, result};
+
+    fn play(state: &State, uci: &str) -> State {
+        result(state, &Action::from_uci(state, uci).unwrap())
+    }
+
+    fn can_claim(state: &State) -> bool {
+        actions(state).contains(&Action::Tie)
+    }
+
+    #[test]
+    fn fifty_move_rule() {
+        let state = State::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 99 80").unwrap();
+        assert!(!can_claim(&state));
+        assert_eq!(value(&state), GameResult::Running);
+
+        // The draw can only be claimed once the hundredth ply is played, and the game goes on
+        // without the claim.
+        let state = play(&state, "a1a2");
+        assert_eq!(state.halfmove_clock, 100);
+        assert!(can_claim(&state));
+        assert_eq!(value(&state), GameResult::Running);
+
+        // A capture or a pawn move resets the count.
+        let state = State::from_fen("4k3/8/8/8/8/8/4P3/R3K3 w - - 100 80").unwrap();
+        assert!(can_claim(&state));
+        assert!(!can_claim(&play(&state, "e2e3")));
+    }
+
+    #[test]
+    fn seventy_five_move_rule() {
+        let state = State::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 149 80").unwrap();
+        assert!(can_claim(&state));
+        assert_eq!(value(&state), GameResult::Running);
+
+        let state = play(&state, "a1a2");
+        assert_eq!(
+            value(&state),
+            GameResult::Tie(Termination::SeventyFiveMoveRule)
+        );
+        let state = State::from_fen("4k3/8/8/8/8/8/R7/4K3 b - - 150 80").unwrap();
+        assert_eq!(
+            value(&state),
+            GameResult::Tie(Termination::SeventyFiveMoveRule)
+        );
+    }
+
+    #[test]
+    fn mate_on_the_last_move() {
+        for halfmove_clock in [99, 149].iter() {
+            let fen = format!("6k1/5ppp/8/8/8/8/8/R5K1 w - - {} 80", halfmove_clock);
+            let state = play(&State::from_fen(&fen).unwrap(), "a1a8");
+            assert_eq!(state.halfmove_clock, halfmove_clock + 1);
+            assert_eq!(value(&state), GameResult::WhiteWin(Termination::Checkmate));
+            assert!(actions(&state).is_empty());
+        }
+
+        // So does stalemate.
+        let state = State::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 150 80").unwrap();
+        assert_eq!(value(&state), GameResult::Tie(Termination::Stalemate));
+        assert!(actions(&state).is_empty());
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let cases = [
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", true),
+            ("1n2k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+            // Bishops on squares of the same colour, on either side or both.
+            ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/1B6/2B1K3 w - - 0 1", true),
+            ("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1", false),
+            ("1n2k3/8/8/8/8/8/8/1N2K3 w - - 0 1", false),
+            ("4kb2/8/8/8/8/8/8/1N2K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/P7/4K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", false),
+            ("3qk3/8/8/8/8/8/8/4K3 w - - 0 1", false),
+        ];
+        for (fen, insufficient) in cases.iter() {
+            let state = State::from_fen(fen).unwrap();
+            assert_eq!(is_insufficient_material(&state), *insufficient, "{}", fen);
+            if *insufficient {
+                assert_eq!(
+                    value(&state),
+                    GameResult::Tie(Termination::InsufficientMaterial)
+                );
+            }
+        }
+    }
+
+    // Claims a draw, and checks how the game ends and how that is written in PGN.
+    fn check_claim(state: &State, termination: Termination, text: &str) {
+        assert!(can_claim(state));
+        let game_result = value(&result(state, &Action::Tie));
+        assert_eq!(game_result, GameResult::Tie(termination));
+        assert_eq!(game_result.to_string(), text);
+
+        let mut pgn = Pgn::new(state.clone());
+        pgn.set_result(game_result);
+        assert_eq!(pgn.tag("Result"), Some("1/2-1/2"));
+        assert_eq!(pgn.tag("Termination"), Some("normal"));
+        assert!(pgn.to_string().trim_end().ends_with("1/2-1/2"));
+    }
+
+    #[test]
+    fn claimed_draws() {
+        let mut state = State::from_fen(crate::fen::START_FEN).unwrap();
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"].repeat(2) {
+            assert!(!can_claim(&state));
+            state = play(&state, uci);
+        }
+        check_claim(
+            &state,
+            Termination::ThreefoldRepetition,
+            "Draw by threefold repetition",
+        );
+
+        let state = State::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 100 80").unwrap();
+        check_claim(
+            &state,
+            Termination::FiftyMoveRule,
+            "Draw by the fifty-move rule",
+        );
+
+        // The claim is taken back with the move.
+        let mut state = state;
+        let undo = inplace_result(&mut state, &Action::Tie);
+        inplace_revert(&mut state, undo);
+        assert_eq!(value(&state), GameResult::Running);
     }
 }
@@ -0,0 +1,512 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::action::Action;
+use crate::actions::actions;
+use crate::ai::{ai_move, fallback_action, MoveSource, SearchOptions, DEFAULT_CONTEMPT};
+use crate::book::{BookSelection, OpeningBook, DEFAULT_BOOK_DEPTH};
+use crate::pgn::result_token;
+use crate::result::result;
+use crate::san::line_to_san;
+use crate::state::{State, PC};
+use crate::syzygy::Tablebase;
+use crate::time_manager::TimeBudget;
+use crate::tt::{TranspositionTable, DEFAULT_TT_SIZE_MB, MAX_TT_SIZE_MB};
+use crate::value::{value, GameResult};
+
+const ENGINE_NAME: &str = "rust_chess";
+
+// Iterative deepening stops at this depth unless "sd" sets a lower limit.
+const MAX_SEARCH_DEPTH: u32 = 64;
+const MAX_BOOK_DEPTH: usize = 1000;
+const MAX_THREADS: usize = 256;
+const MAX_CONTEMPT: i32 = 1000;
+
+enum Event {
+    Command(String),
+    BestMove { search_id: u32, action: Action },
+}
+
+// Time control set by "level" or "st". Times are in milliseconds.
+enum TimeControl {
+    Conventional {
+        moves_per_session: u64,
+        // The time on the engine's clock at the start of a game, if known.
+        base: Option<u64>,
+        increment: u64,
+    },
+    FixedPerMove(u64),
+}
+
+struct Engine {
+    history: Vec<State>,
+    // The side the engine plays, or None while in force mode.
+    engine_color: Option<PC>,
+    time_control: TimeControl,
+    max_depth: u32,
+    engine_time: Option<u64>,
+    post: bool,
+    // The search whose result is still wanted, with the channel used to stop it.
+    search: Option<(u32, Sender<()>)>,
+    next_search_id: u32,
+    // Kept between searches, so that later moves can reuse what earlier searches found.
+    tt: Arc<TranspositionTable>,
+    book: Option<Arc<OpeningBook>>,
+    book_depth: usize,
+    book_selection: BookSelection,
+    tablebase: Option<Arc<Tablebase>>,
+    search_options: SearchOptions,
+}
+
+// Parses the base time of a "level" command, given either as minutes or as minutes:seconds.
+fn parse_base_time(base: &str) -> Option<u64> {
+    let mut parts = base.splitn(2, ':');
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = match parts.next() {
+        Some(seconds) => seconds.parse::<u64>().ok()?,
+        None => 0,
+    };
+
+    minutes
+        .checked_mul(60)?
+        .checked_add(seconds)?
+        .checked_mul(1000)
+}
+
+impl Engine {
+    fn new() -> Self {
+        Engine {
+            history: vec![State::new()],
+            engine_color: Some(PC::Black),
+            time_control: TimeControl::Conventional {
+                moves_per_session: 0,
+                base: None,
+                increment: 0,
+            },
+            max_depth: MAX_SEARCH_DEPTH,
+            engine_time: None,
+            post: false,
+            search: None,
+            next_search_id: 0,
+            tt: Arc::new(TranspositionTable::new(DEFAULT_TT_SIZE_MB)),
+            book: None,
+            book_depth: DEFAULT_BOOK_DEPTH,
+            book_selection: BookSelection::WeightedRandom,
+            tablebase: None,
+            search_options: SearchOptions::default(),
+        }
+    }
+
+    fn state(&self) -> &State {
+        &self.history[self.history.len() - 1]
+    }
+
+    fn time_budget(&self) -> Option<TimeBudget> {
+        match self.time_control {
+            TimeControl::FixedPerMove(time) => Some(TimeBudget::fixed(Duration::from_millis(time))),
+            TimeControl::Conventional {
+                moves_per_session,
+                increment,
+                ..
+            } => {
+                let time = self.engine_time?;
+                let moves_to_go = if moves_per_session == 0 {
+                    None
+                } else {
+                    let engine_moves = (self.state().moves / 2) as u64;
+                    Some((moves_per_session - engine_moves % moves_per_session) as u32)
+                };
+                Some(TimeBudget::from_clock(
+                    Duration::from_millis(time),
+                    Duration::from_millis(increment),
+                    moves_to_go,
+                ))
+            }
+        }
+    }
+
+    // Abandons the current search, if any; its result will be ignored.
+    fn cancel_search(&mut self) {
+        if let Some((_, stop_tx)) = self.search.take() {
+            let _ = stop_tx.send(());
+        }
+    }
+
+    fn start_search(&mut self, events: &Sender<Event>) {
+        self.cancel_search();
+
+        let state = self.state().clone();
+        if actions(&state).is_empty() {
+            return;
+        }
+
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let budget = self.time_budget();
+
+        let search_id = self.next_search_id;
+        self.next_search_id += 1;
+        self.search = Some((search_id, stop_tx));
+
+        let (max_depth, post, options, events) = (
+            self.max_depth,
+            self.post,
+            self.search_options,
+            events.clone(),
+        );
+        let (tt, book, tablebase) = (self.tt.clone(), self.book.clone(), self.tablebase.clone());
+        thread::spawn(move || {
+            let (tx, rx) = mpsc::channel();
+            let search_state = state.clone();
+            let search_tt = tt.clone();
+            // A cancelled search may still be running, in which case both share the table until it
+            // notices.
+            let searcher = thread::spawn(move || {
+                ai_move(
+                    search_state,
+                    tx,
+                    max_depth,
+                    budget,
+                    stop_rx,
+                    book.as_deref(),
+                    tablebase.as_deref(),
+                    options,
+                    &search_tt,
+                );
+            });
+
+            let mut best_action = None;
+            for info in rx {
+                // Book and tablebase moves are not searched, so there is nothing to post for them.
+                if post && info.source == MoveSource::Search {
+                    println!(
+                        "{} {} {} {} {}",
+                        info.depth,
+                        // Mates are posted as 100000 + N for mate in N moves, negated when mated.
+                        match info.mate_in() {
+                            Some(moves) if moves > 0 => 100000 + moves,
+                            Some(moves) => -100000 + moves,
+                            None => info.utility,
+                        },
+                        info.elapsed.as_millis() / 10,
+                        info.nodes,
+                        line_to_san(&state, &info.pv)
+                    );
+                }
+                best_action = Some(info.action);
+            }
+            let _ = searcher.join();
+
+            // Sending nothing would lose on time, so a search that reported nothing still moves.
+            let best_action = best_action.or_else(|| fallback_action(&state, &tt));
+            if let Some(action) = best_action {
+                let _ = events.send(Event::BestMove { search_id, action });
+            }
+        });
+    }
+
+    // A draw claim is not a move on the board, so only the result is sent for it.
+    fn play_engine_move(&mut self, action: Action) {
+        let new_state = result(self.state(), &action);
+        if action != Action::Tie {
+            println!("move {}", action.to_uci());
+        }
+
+        let game_result = value(&new_state);
+        if game_result != GameResult::Running {
+            println!("{} {{{}}}", result_token(game_result), game_result);
+        }
+        self.history.push(new_state);
+    }
+
+    fn is_engine_turn(&self) -> bool {
+        self.engine_color == Some(self.state().turn)
+    }
+
+    // Handles a single command, returning false when the engine should exit.
+    fn handle_command(&mut self, line: &str, events: &Sender<Event>) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+
+        match command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "name" | "rating" | "draw" | "otim" => (),
+            "protover" => {
+                println!("feature option=\"Book File -file \"");
+                println!(
+                    "feature option=\"Book Depth -spin {} 0 {}\"",
+                    DEFAULT_BOOK_DEPTH, MAX_BOOK_DEPTH
+                );
+                println!("feature option=\"Best Book Move -check 0\"");
+                println!(
+                    "feature option=\"Contempt -spin {} {} {}\"",
+                    DEFAULT_CONTEMPT, -MAX_CONTEMPT, MAX_CONTEMPT
+                );
+                for name in SearchOptions::SWITCHES.iter() {
+                    println!("feature option=\"{} -check 1\"", name);
+                }
+                println!(
+                    "feature myname=\"{}\" ping=1 setboard=1 usermove=1 playother=1 san=0 \
+                     time=1 draw=0 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 memory=1 smp=1 \
+                     egt=\"syzygy\" done=1",
+                    ENGINE_NAME
+                );
+            }
+            "new" => {
+                self.cancel_search();
+                self.history = vec![State::new()];
+                self.engine_color = Some(PC::Black);
+                self.max_depth = MAX_SEARCH_DEPTH;
+                self.engine_time = match self.time_control {
+                    TimeControl::Conventional { base, .. } => base,
+                    TimeControl::FixedPerMove(_) => None,
+                };
+                self.tt.clear();
+            }
+            "force" | "result" => {
+                self.cancel_search();
+                self.engine_color = None;
+            }
+            "go" => {
+                self.engine_color = Some(self.state().turn);
+                self.start_search(events);
+            }
+            "playother" => {
+                self.cancel_search();
+                self.engine_color = Some(self.state().turn.opponent());
+            }
+            "?" => {
+                if let Some((_, stop_tx)) = &self.search {
+                    let _ = stop_tx.send(());
+                }
+            }
+            "setboard" => {
+                self.cancel_search();
+                match State::from_fen(&args.join(" ")) {
+                    Ok(state) => self.history = vec![state],
+                    Err(e) => println!("tellusererror Illegal position: {}", e),
+                }
+            }
+            "level" => {
+                if let [moves_per_session, base, increment] = args {
+                    let parsed = (
+                        moves_per_session.parse::<u64>(),
+                        parse_base_time(base),
+                        increment.parse::<f64>(),
+                    );
+                    match parsed {
+                        (Ok(moves_per_session), Some(base), Ok(increment)) => {
+                            self.time_control = TimeControl::Conventional {
+                                moves_per_session,
+                                base: Some(base),
+                                increment: (increment * 1000.) as u64,
+                            };
+                            // Until "time" says otherwise, the clock starts out full.
+                            self.engine_time = Some(base);
+                        }
+                        _ => println!("Error (bad arguments): {}", line),
+                    }
+                } else {
+                    println!("Error (bad arguments): {}", line);
+                }
+            }
+            "st" => match args.first().and_then(|s| s.parse::<f64>().ok()) {
+                Some(seconds) => {
+                    self.time_control = TimeControl::FixedPerMove((seconds * 1000.) as u64)
+                }
+                None => println!("Error (bad arguments): {}", line),
+            },
+            "sd" => match args.first().and_then(|s| s.parse::<u32>().ok()) {
+                Some(depth) => self.max_depth = depth.max(1),
+                None => println!("Error (bad arguments): {}", line),
+            },
+            "time" => {
+                // The engine's clock, in centiseconds.
+                if let Some(centiseconds) = args.first().and_then(|s| s.parse::<i64>().ok()) {
+                    self.engine_time = Some(centiseconds.max(0) as u64 * 10);
+                }
+            }
+            "undo" | "remove" => {
+                self.cancel_search();
+                let count = if command == "undo" { 1 } else { 2 };
+                for _ in 0..count {
+                    if self.history.len() > 1 {
+                        self.history.pop();
+                    }
+                }
+            }
+            "memory" => match args.first().and_then(|s| s.parse::<usize>().ok()) {
+                // The size is for all of the engine's tables, of which there is only one.
+                // A cancelled search keeps the old table until it stops.
+                Some(size_mb) => {
+                    self.cancel_search();
+                    let size_mb = size_mb.clamp(1, MAX_TT_SIZE_MB);
+                    self.tt = Arc::new(TranspositionTable::new(size_mb));
+                }
+                None => println!("Error (bad arguments): {}", line),
+            },
+            "cores" => match args.first().and_then(|s| s.parse::<usize>().ok()) {
+                Some(cores) => self.search_options.threads = cores.clamp(1, MAX_THREADS),
+                None => println!("Error (bad arguments): {}", line),
+            },
+            "egtpath" => match args.split_first() {
+                Some((&"syzygy", path)) => {
+                    self.cancel_search();
+                    let path = path.join(" ");
+                    match Tablebase::open(&path) {
+                        Ok(tablebase) => self.tablebase = Some(Arc::new(tablebase)),
+                        Err(e) => {
+                            println!("tellusererror Could not read tablebases {}: {}", path, e)
+                        }
+                    }
+                }
+                _ => println!("Error (unsupported tablebases): {}", line),
+            },
+            "option" => match args.join(" ").split_once('=') {
+                Some((name, value)) => self.set_option(name, value),
+                None => println!("Error (bad arguments): {}", line),
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => println!("pong {}", args.first().unwrap_or(&"")),
+            "quit" => return false,
+            "usermove" => match args.first() {
+                Some(m) => self.user_move(m, events),
+                None => println!("Error (bad arguments): {}", line),
+            },
+            _ => {
+                if Action::from_uci(self.state(), command).is_some() {
+                    self.user_move(command, events);
+                } else {
+                    println!("Error (unknown command): {}", command);
+                }
+            }
+        }
+
+        true
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name {
+            "Book File" if value.is_empty() => self.book = None,
+            "Book File" => match OpeningBook::open(value) {
+                Ok(mut book) => {
+                    book.depth = self.book_depth;
+                    book.selection = self.book_selection;
+                    self.book = Some(Arc::new(book));
+                }
+                Err(e) => println!("tellusererror Could not read book {}: {}", value, e),
+            },
+            "Book Depth" => match value.parse::<usize>() {
+                Ok(depth) => self.book_depth = depth.min(MAX_BOOK_DEPTH),
+                Err(_) => println!("Error (bad arguments): option {}={}", name, value),
+            },
+            "Best Book Move" => {
+                self.book_selection = if value == "1" {
+                    BookSelection::BestWeight
+                } else {
+                    BookSelection::WeightedRandom
+                }
+            }
+            "Contempt" => match value.parse::<i32>() {
+                Ok(contempt) => {
+                    self.search_options.contempt = contempt.clamp(-MAX_CONTEMPT, MAX_CONTEMPT)
+                }
+                Err(_) => println!("Error (bad arguments): option {}={}", name, value),
+            },
+            _ => match self.search_options.switch_mut(name) {
+                Some(switch) => *switch = value == "1",
+                None => println!("Error (unknown option): {}", name),
+            },
+        }
+
+        if let Some(book) = &mut self.book {
+            let book = Arc::make_mut(book);
+            book.depth = self.book_depth;
+            book.selection = self.book_selection;
+        }
+    }
+
+    fn user_move(&mut self, m: &str, events: &Sender<Event>) {
+        match Action::from_uci(self.state(), m) {
+            Some(action) => {
+                self.cancel_search();
+                let new_state = result(self.state(), &action);
+                self.history.push(new_state);
+                if self.is_engine_turn() && value(self.state()) == GameResult::Running {
+                    self.start_search(events);
+                }
+            }
+            None => println!("Illegal move: {}", m),
+        }
+    }
+
+    fn handle_best_move(&mut self, search_id: u32, action: Action) {
+        match self.search {
+            Some((id, _)) if id == search_id => (),
+            _ => return,
+        }
+        self.search = None;
+
+        if self.is_engine_turn() {
+            self.play_engine_move(action);
+        }
+    }
+}
+
+// Runs the Chess Engine Communication Protocol (xboard/WinBoard) over stdin and stdout.
+pub fn xboard_loop() {
+    let (events_tx, events_rx) = mpsc::channel();
+
+    let stdin_tx = events_tx.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if stdin_tx.send(Event::Command(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = stdin_tx.send(Event::Command("quit".to_string()));
+    });
+
+    let mut engine = Engine::new();
+    for event in events_rx.iter() {
+        match event {
+            Event::Command(line) => {
+                if !engine.handle_command(&line, &events_tx) {
+                    break;
+                }
+            }
+            Event::BestMove { search_id, action } => engine.handle_best_move(search_id, action),
+        }
+    }
+
+    engine.cancel_search();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_base_time() {
+        assert_eq!(parse_base_time("5"), Some(300_000));
+        assert_eq!(parse_base_time("0:30"), Some(30_000));
+        assert_eq!(parse_base_time("2:05"), Some(125_000));
+        assert_eq!(parse_base_time("x"), None);
+        assert_eq!(parse_base_time("1:x"), None);
+        assert_eq!(parse_base_time("-1"), None);
+        assert_eq!(parse_base_time(&u64::MAX.to_string()), None);
+        assert_eq!(parse_base_time(&format!("0:{}", u64::MAX)), None);
+        assert_eq!(parse_base_time(&format!("{}", u64::MAX / 60_000 + 1)), None);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::bitboard::{bit, color_index, piece_index, square, PAWN};
+use crate::state::{Piece, State, PC};
+
+// Random keys for every feature of a position. The hash of a position is the xor of the keys of
+// its features, so making a move only needs to xor in the keys that changed.
+struct Keys {
+    pieces: [[[u64; 64]; 6]; 2],
+    black_to_move: u64,
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+}
+
+// SplitMix64, which is good enough to fill the table and can run at compile time.
+const fn next_random(seed: u64) -> (u64, u64) {
+    let seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (seed, z ^ (z >> 31))
+}
+
+const fn generate_keys() -> Keys {
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 6]; 2],
+        black_to_move: 0,
+        castling: [0; 4],
+        en_passant: [0; 8],
+    };
+    let mut seed = 0x2545_f491_4f6c_dd1d;
+    let mut random;
+
+    let mut c = 0;
+    while c < 2 {
+        let mut t = 0;
+        while t < 6 {
+            let mut sq = 0;
+            while sq < 64 {
+                (seed, random) = next_random(seed);
+                keys.pieces[c][t][sq] = random;
+                sq += 1;
+            }
+            t += 1;
+        }
+        c += 1;
+    }
+
+    (seed, random) = next_random(seed);
+    keys.black_to_move = random;
+
+    let mut i = 0;
+    while i < 4 {
+        (seed, random) = next_random(seed);
+        keys.castling[i] = random;
+        i += 1;
+    }
+
+    let mut x = 0;
+    while x < 8 {
+        (seed, random) = next_random(seed);
+        keys.en_passant[x] = random;
+        x += 1;
+    }
+
+    keys
+}
+
+const KEYS: Keys = generate_keys();
+
+pub fn piece_key(piece: &Piece, square: usize) -> u64 {
+    KEYS.pieces[color_index(piece.c)][piece_index(piece.t)][square]
+}
+
+pub fn black_to_move_key() -> u64 {
+    KEYS.black_to_move
+}
+
+// The file of the pawn that jumped on the previous move, if the side to move has a pawn that could
+// capture it. Positions which allow the same moves then hash the same.
+pub fn capturable_en_passant_file(state: &State) -> Option<usize> {
+    let x = state.en_passant_file()?;
+    let y = match state.turn {
+        PC::White => 3,
+        PC::Black => 4,
+    };
+
+    let mut capturers = 0;
+    if x > 0 {
+        capturers |= bit(square(y, x - 1));
+    }
+    if x < 7 {
+        capturers |= bit(square(y, x + 1));
+    }
+    if capturers & state.bitboards.of(state.turn, PAWN) != 0 {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+fn en_passant_key(state: &State) -> u64 {
+    match capturable_en_passant_file(state) {
+        Some(x) => KEYS.en_passant[x],
+        None => 0,
+    }
+}
+
+// Keys of the castling rights and en-passant file, which inplace_result xors out before a move and
+// back in after it.
+pub fn rights_key(state: &State) -> u64 {
+    let castling = state
+        .castling_rights()
+        .iter()
+        .zip(KEYS.castling.iter())
+        .filter(|(right, _)| **right)
+        .fold(0, |acc, (_, key)| acc ^ key);
+
+    castling ^ en_passant_key(state)
+}
+
+// Computes the hash from scratch; after that it is kept up to date by set_square and
+// inplace_result.
+pub fn full_key(state: &State) -> u64 {
+    let mut key = rights_key(state);
+    if state.turn == PC::Black {
+        key ^= black_to_move_key();
+    }
+
+    for (y, row) in state.board.iter().enumerate() {
+        for (x, piece) in row.iter().enumerate() {
+            if let Some(piece) = piece {
+                key ^= piece_key(piece, square(y, x));
+            }
+        }
+    }
+
+    key
+}
@@ -0,0 +1,143 @@
+// Reference node counts from https://www.chessprogramming.org/Perft_Results.
+use rust_chess::actions::actions;
+use rust_chess::fen::START_FEN;
+use rust_chess::perft::perft;
+use rust_chess::result::{inplace_null_move, inplace_null_revert, inplace_result, inplace_revert};
+use rust_chess::state::State;
+use rust_chess::zobrist::full_key;
+
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+const POSITION_4_MIRRORED: &str =
+    "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+fn check(fen: &str, expected: &[u64]) {
+    let state = State::from_fen(fen).unwrap();
+    for (depth, &nodes) in expected.iter().enumerate() {
+        let depth = depth as u32 + 1;
+        assert_eq!(perft(&state, depth), nodes, "{} at depth {}", fen, depth);
+    }
+}
+
+#[test]
+fn start_position() {
+    check(START_FEN, &[20, 400, 8902, 197_281]);
+}
+
+// Slow without optimizations; run with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn start_position_deep() {
+    check(START_FEN, &[20, 400, 8902, 197_281, 4_865_609]);
+}
+
+#[test]
+fn kiwipete() {
+    check(KIWIPETE, &[48, 2039, 97_862]);
+}
+
+#[test]
+#[ignore]
+fn kiwipete_deep() {
+    check(KIWIPETE, &[48, 2039, 97_862, 4_085_603]);
+}
+
+#[test]
+fn position_3() {
+    check(POSITION_3, &[14, 191, 2812, 43_238]);
+}
+
+#[test]
+#[ignore]
+fn position_3_deep() {
+    check(POSITION_3, &[14, 191, 2812, 43_238, 674_624]);
+}
+
+#[test]
+fn position_4() {
+    check(POSITION_4, &[6, 264, 9467]);
+}
+
+#[test]
+#[ignore]
+fn position_4_deep() {
+    check(POSITION_4, &[6, 264, 9467, 422_333]);
+}
+
+#[test]
+fn position_4_mirrored() {
+    check(POSITION_4_MIRRORED, &[6, 264, 9467]);
+}
+
+#[test]
+#[ignore]
+fn position_4_mirrored_deep() {
+    check(POSITION_4_MIRRORED, &[6, 264, 9467, 422_333]);
+}
+
+#[test]
+fn position_5() {
+    check(POSITION_5, &[44, 1486, 62_379]);
+}
+
+#[test]
+#[ignore]
+fn position_5_deep() {
+    check(POSITION_5, &[44, 1486, 62_379, 2_103_487]);
+}
+
+#[test]
+fn position_6() {
+    check(POSITION_6, &[46, 2079, 89_890]);
+}
+
+#[test]
+#[ignore]
+fn position_6_deep() {
+    check(POSITION_6, &[46, 2079, 89_890, 3_894_594]);
+}
+
+// Checks the key that is updated move by move against the one computed from scratch, after every
+// move, draw claim and null move and after taking each of them back.
+fn check_keys(state: &mut State, depth: u32) {
+    assert_eq!(state.hash_key, full_key(state));
+    if depth == 0 {
+        return;
+    }
+
+    for a in actions(state) {
+        let before = state.hash_key;
+        let undo = inplace_result(state, &a);
+        check_keys(state, depth - 1);
+        inplace_revert(state, undo);
+        assert_eq!(state.hash_key, before);
+        assert_eq!(state.hash_key, full_key(state));
+    }
+
+    let undo = inplace_null_move(state);
+    assert_eq!(state.hash_key, full_key(state));
+    inplace_null_revert(state, undo);
+    assert_eq!(state.hash_key, full_key(state));
+}
+
+#[test]
+fn incremental_keys() {
+    let fens = [
+        START_FEN,
+        KIWIPETE,
+        POSITION_3,
+        POSITION_4,
+        POSITION_4_MIRRORED,
+        POSITION_5,
+        POSITION_6,
+        // En passant captures, one of them leaving the king in check.
+        "8/8/8/KPp4r/8/8/8/7k w - c6 0 2",
+        "4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1",
+    ];
+    for fen in fens.iter() {
+        check_keys(&mut State::from_fen(fen).unwrap(), 3);
+    }
+}